@@ -0,0 +1,146 @@
+use std::{future::ready, sync::Arc, time::Duration};
+
+use axum::{
+    extract::{rejection::JsonRejection, State}, http::HeaderMap, routing::{get, post}, Json, Router
+};
+use http::header::AUTHORIZATION;
+use metrics_exporter_prometheus::PrometheusHandle;
+use serde::Deserialize;
+
+use crate::{
+    api_error::ApiError,
+    configs::AdminServer,
+    health::{self, HealthChecker},
+    http_layers,
+    takedown::{Takedown, TakedownReport, TakedownTarget},
+};
+
+/// Creates an HTTP server for internal endpoints: metrics, health checks and administration.
+/// It is supposed to listen on a private interface, not exposed to the internet.
+pub async fn run_admin_server(
+    cfg: &AdminServer,
+    recorder_handle: Option<PrometheusHandle>,
+    health_checker: Arc<HealthChecker>,
+    takedown: Arc<Takedown>,
+) -> anyhow::Result<()> {
+    let mut app = Router::new()
+        .route("/health/live", get(health::liveness_handler))
+        .route("/health/ready", get(health::readiness_handler));
+    if let Some(recorder_handle) = recorder_handle {
+        app = app.route("/metrics", get(move || { ready(recorder_handle.render())}));
+    }
+    let mut app = app.with_state(health_checker);
+    if let Some(api_token) = &cfg.api_token {
+        app = app.merge(admin_api(api_token, takedown));
+    }
+    let app = http_layers::with_middleware(
+        app,
+        Duration::from_secs(cfg.request_timeout_secs),
+        cfg.max_request_body_bytes,
+    );
+
+    let listener = tokio::net::TcpListener::bind((cfg.bind_address.as_str(), cfg.port)).await?;
+    tracing::info!(bind_address = %cfg.bind_address, port = cfg.port, "Admin HTTP server is listening");
+    axum::serve(listener, app).await?;
+
+    Ok(())
+}
+
+#[derive(Clone)]
+struct AdminApiState {
+    api_token: Arc<str>,
+    takedown: Arc<Takedown>,
+}
+
+/// Endpoints that change the stored data, they require the `admin_server.api_token` bearer token
+fn admin_api(api_token: &str, takedown: Arc<Takedown>) -> Router {
+    Router::new()
+        .route("/takedown", post(take_down))
+        .with_state(AdminApiState { api_token: Arc::from(api_token), takedown })
+}
+
+/// Asset is identified either by its ID or by its URL
+#[derive(Deserialize)]
+struct TakedownRequest {
+    id: Option<String>,
+    url: Option<String>,
+    reason: String,
+}
+
+/// Deletes the asset preview and adds it to the denylist, see [crate::takedown]
+async fn take_down(
+    State(state): State<AdminApiState>,
+    headers: HeaderMap,
+    body: Result<Json<TakedownRequest>, JsonRejection>,
+) -> Result<Json<TakedownReport>, ApiError> {
+    authorize(&headers, &state.api_token)?;
+    let Json(request) = body.map_err(|e| ApiError::BadRequest(e.body_text()))?;
+    let target = match (request.id, request.url) {
+        (Some(id), None) => TakedownTarget::Id(id),
+        (None, Some(url)) => TakedownTarget::Url(url),
+        _ => return Err(ApiError::BadRequest("Exactly one of id and url is required".to_string())),
+    };
+    if request.reason.trim().is_empty() {
+        return Err(ApiError::BadRequest("Reason is required".to_string()));
+    }
+    Ok(Json(state.takedown.take_down(target, &request.reason).await?))
+}
+
+fn authorize(headers: &HeaderMap, api_token: &str) -> Result<(), ApiError> {
+    let token = headers.get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match token {
+        Some(token) if constant_time_eq(token.as_bytes(), api_token.as_bytes()) => Ok(()),
+        _ => Err(ApiError::Unauthorized),
+    }
+}
+
+/// Compares all the bytes, so that the response time doesn't tell how much of the token is right
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::{body::{to_bytes, Body}, http::{Request, StatusCode}};
+    use tower::ServiceExt;
+
+    use crate::media_store::{self, Denied, MediaStore, MemoryMediaStore};
+
+    const TOKEN: &str = "0123456789abcdef0123456789abcdef";
+
+    async fn post_takedown(app: Router, token: &str, body: &str) -> (StatusCode, serde_json::Value) {
+        let request = Request::post("/takedown")
+            .header(AUTHORIZATION, format!("Bearer {token}"))
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let resp = app.oneshot(request).await.unwrap();
+        let status = resp.status();
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_takedown_endpoint() {
+        let store: Arc<dyn MediaStore + Send + Sync> = Arc::new(MemoryMediaStore::default());
+        let app = admin_api(TOKEN, Arc::new(Takedown::new(store.clone(), None, None)));
+
+        let (status, body) = post_takedown(app.clone(), "wrong", r#"{"id": "asset1", "reason": "DMCA"}"#).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(body["error"], "unauthorized");
+
+        let (status, body) = post_takedown(app.clone(), TOKEN, r#"{"id": "asset1", "url": "https://a/1.png", "reason": "DMCA"}"#).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(body["error"], "bad_request");
+        let (status, _) = post_takedown(app.clone(), TOKEN, r#"{"id": "asset1"}"#).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+
+        let (status, body) = post_takedown(app, TOKEN, r#"{"id": "asset1", "reason": "DMCA"}"#).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["id"], "asset1");
+        assert!(media_store::is_denied(store.as_ref(), Denied::Url, "asset1").await.unwrap());
+    }
+}
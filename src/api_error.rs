@@ -0,0 +1,64 @@
+use axum::{http::StatusCode, response::{IntoResponse, Response}, Json};
+use serde::Serialize;
+
+use crate::media_store::StorageError;
+
+/// Error returned by HTTP handlers and middleware,
+/// rendered as a JSON body with a matching status code
+#[derive(Debug)]
+pub enum ApiError {
+    NotFound,
+    RouteNotFound,
+    /// Missing or wrong credentials of an administration endpoint
+    Unauthorized,
+    BadRequest(String),
+    StorageUnavailable,
+    Timeout,
+    PayloadTooLarge,
+    Internal(String),
+}
+
+#[derive(Serialize)]
+struct ErrorBody {
+    error: &'static str,
+    message: String,
+}
+
+impl From<StorageError> for ApiError {
+    fn from(value: StorageError) -> Self {
+        match value {
+            StorageError::NotFound => ApiError::NotFound,
+            StorageError::Unavailable(details) => {
+                tracing::warn!(error = %details, "Media storage is unavailable");
+                ApiError::StorageUnavailable
+            },
+            err @ (StorageError::Forbidden | StorageError::Other(_)) => ApiError::Internal(err.to_string()),
+        }
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let (status, error, message) = match self {
+            ApiError::NotFound =>
+                (StatusCode::NOT_FOUND, "not_found", "Asset not found".to_string()),
+            ApiError::RouteNotFound =>
+                (StatusCode::NOT_FOUND, "route_not_found", "No such endpoint".to_string()),
+            ApiError::Unauthorized =>
+                (StatusCode::UNAUTHORIZED, "unauthorized", "Valid bearer token is required".to_string()),
+            ApiError::BadRequest(details) =>
+                (StatusCode::BAD_REQUEST, "bad_request", details),
+            ApiError::StorageUnavailable =>
+                (StatusCode::SERVICE_UNAVAILABLE, "storage_unavailable", "Media storage is temporarily unavailable".to_string()),
+            ApiError::Timeout =>
+                (StatusCode::REQUEST_TIMEOUT, "timeout", "Request processing took too long".to_string()),
+            ApiError::PayloadTooLarge =>
+                (StatusCode::PAYLOAD_TOO_LARGE, "payload_too_large", "Request body is too large".to_string()),
+            ApiError::Internal(details) => {
+                tracing::error!(error = %details, "Request failed");
+                (StatusCode::INTERNAL_SERVER_ERROR, "internal_error", "Internal server error".to_string())
+            },
+        };
+        (status, Json(ErrorBody { error, message })).into_response()
+    }
+}
@@ -1,16 +1,95 @@
-use metrics_exporter_prometheus::{PrometheusBuilder, PrometheusHandle};
+use std::{net::SocketAddr, time::Duration};
+
+use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
 use sysinfo::System;
 
+use crate::configs::MetricsCfg;
+
 pub const MET_DOWNLOADS: &str = "downloads";
+/// Histogram: time of an HTTP request processing
+pub const MET_HTTP_REQUEST_DURATION: &str = "http_request_duration_seconds";
+/// Histogram: time of an object storage operation
+pub const MET_STORAGE_OPERATION_DURATION: &str = "storage_operation_duration_seconds";
+/// Histogram: time of an asset download
+pub const MET_DOWNLOAD_DURATION: &str = "download_duration_seconds";
+/// Histogram: time of an image processing phase
+pub const MET_IMAGE_PROCESSING_DURATION: &str = "image_processing_duration_seconds";
+/// Histogram: time of the whole URL processing, from download till persisting the preview
+pub const MET_ASSET_PROCESSING_DURATION: &str = "asset_processing_duration_seconds";
+/// Histogram: time of DAS node gRPC calls
+pub const MET_DAS_REQUEST_DURATION: &str = "das_request_duration_seconds";
+/// Counter: bytes of assets downloaded from the internet
+pub const MET_BYTES_DOWNLOADED: &str = "downloaded_bytes_total";
+/// Counter: bytes written to the object storage
+pub const MET_BYTES_STORED: &str = "stored_bytes_total";
+/// Counter: bytes of previews sent to HTTP clients
+pub const MET_BYTES_SERVED: &str = "served_bytes_total";
+/// Counter: URL processing results submitted to DAS node
+pub const MET_RESULTS_SUBMITTED: &str = "das_submitted_results_total";
+/// Counter: downloaded images by whether their content has been stored already (`duplicate`) or not (`unique`)
+pub const MET_CONTENT_DEDUP: &str = "content_dedup_total";
+/// Counter: downloaded originals by whether they have been archived (`archived`, `too_large`, `failed`)
+pub const MET_ORIGINALS_ARCHIVED: &str = "originals_archived_total";
+/// Counter: operations replicated to the secondary storages (`success`, `error`, or `dropped` if the queue is full)
+pub const MET_REPLICATION: &str = "storage_replication_total";
+/// Counter: reads served by a replica (`success`) or failed everywhere (`error`) after the primary storage has failed
+pub const MET_READ_FALLBACKS: &str = "storage_read_fallbacks_total";
+/// Counter: URLs blocked by the URL filter, by the rule that has blocked them
+pub const MET_URLS_BLOCKED: &str = "urls_blocked_total";
+
 pub const CAT_STATUS: &str = "status";
+pub const CAT_OUTCOME: &str = "outcome";
+pub const CAT_OPERATION: &str = "operation";
+pub const CAT_ROUTE: &str = "route";
+pub const CAT_PHASE: &str = "phase";
+pub const CAT_REPLICA: &str = "replica";
+pub const CAT_RULE: &str = "rule";
+
+/// Buckets for all the `*_duration_seconds` histograms
+const DURATION_BUCKETS: &[f64] = &[
+    0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0,
+];
+
+/// Installs global metrics recorder, and if configured, starts the exporter
+/// that either listens on a dedicated address, or pushes metrics to a push gateway.
+/// Regardless of the exporter, the returned handle can be used to render metrics.
+pub fn setup_metrics_recorder(cfg: &MetricsCfg) -> anyhow::Result<PrometheusHandle> {
+    let mut builder = PrometheusBuilder::new()
+        .set_buckets_for_metric(Matcher::Suffix("_duration_seconds".to_string()), DURATION_BUCKETS)?;
+    for (name, value) in &cfg.global_labels {
+        builder = builder.add_global_label(name, value);
+    }
+
+    let builder = match (&cfg.push_gateway, &cfg.listen_address) {
+        (Some(_), Some(_)) => anyhow::bail!("Metrics cannot be both pushed to a gateway and served on a listen address"),
+        (Some(push_gateway), None) => builder.with_push_gateway(
+            &push_gateway.endpoint,
+            Duration::from_secs(push_gateway.interval_secs),
+            push_gateway.username.clone(),
+            push_gateway.password.clone(),
+        )?,
+        (None, Some(listen_address)) => builder.with_http_listener(listen_address.parse::<SocketAddr>()?),
+        (None, None) => return Ok(builder.install_recorder()?),
+    };
+
+    let (recorder, exporter) = builder.build()?;
+    let handle = recorder.handle();
+    metrics::set_global_recorder(recorder)?;
+    tokio::spawn(async move {
+        if exporter.await.is_err() {
+            tracing::error!("Metrics exporter has failed");
+        }
+    });
+
+    Ok(handle)
+}
 
-pub fn setup_metrics_recorder() -> PrometheusHandle {
-    PrometheusBuilder::new()
-        .install_recorder()
-        .unwrap()
+/// Label value for an operation result
+pub fn outcome<T, E>(result: &Result<T, E>) -> &'static str {
+    if result.is_ok() { "success" } else { "error" }
 }
 
-pub async fn run_sys_metrics_collector() {
+pub async fn run_sys_metrics_collector(interval: Duration) {
     tokio::spawn(async move {
         let mut sys = System::new();
         loop {
@@ -28,7 +107,7 @@ pub async fn run_sys_metrics_collector() {
                 metrics::gauge!("cpu", "name" => cpu.name().to_string()).set(cpu.cpu_usage());
             }
 
-            tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+            tokio::time::sleep(interval).await;
         }
     });
 }
@@ -1,6 +1,6 @@
-use std::sync::Arc;
+use std::{sync::Arc, time::Duration};
 
-use crate::{app_metrics, asset_processing, configs::Settings, das_client::UtilityChainClient, http_endpoints, obj_storage_client::MediaStorageClient};
+use crate::{admin_endpoints, app_metrics, asset_processing, config_reload::ReloadableSettings, configs::Settings, das_client::{DasClient, UtilityChainClient}, health::HealthChecker, http_endpoints, media_store, takedown::Takedown};
 
 pub struct App {
 }
@@ -8,28 +8,71 @@ pub struct App {
 impl App {
     /// This is the main assembly point for the media-service application.
     /// In starts the URL fetcher that continuously queries DAS node for new URLs to download,
-    /// and HTTP server for providing assets preview images.
-    pub async fn start(app_cfg: &Settings) {
-        let media_storag_client = Arc::new(MediaStorageClient::new(&app_cfg.obj_storage).await);
+    /// HTTP server for providing assets preview images, and admin HTTP server
+    /// for metrics and health checks.
+    ///
+    /// `reloadable` provides the pipeline settings that may change while the application is running.
+    pub async fn start(app_cfg: &Settings, reloadable: ReloadableSettings) -> anyhow::Result<()> {
+        let recorder_handle = if app_cfg.metrics.enabled {
+            Some(app_metrics::setup_metrics_recorder(&app_cfg.metrics)?)
+        } else {
+            None
+        };
 
-        if app_cfg.das.enabled {
+        let media_storag_client = media_store::create_media_store(app_cfg, media_store::QueueOverflow::Drop).await?;
+        let originals = media_store::create_originals_archive(app_cfg, &media_storag_client).await?;
+
+        let mut health_checker = HealthChecker {
+            media_storage: media_storag_client.clone(),
+            das_client: None,
+            pipeline: None,
+            cfg: app_cfg.health.clone(),
+        };
+
+        let das_client = app_cfg.das.enabled.then(|| -> Arc<dyn DasClient + Send + Sync> {
+            Arc::new(UtilityChainClient { das_url: app_cfg.das.grpc_address.clone() })
+        });
+        let takedown = Arc::new(Takedown::new(media_storag_client.clone(), originals.clone(), das_client.clone()));
+
+        if let Some(das_client) = das_client {
             // Rollup NFTs downloader
-            let das_client = UtilityChainClient { das_url: app_cfg.das.grpc_address.clone() };
-            asset_processing::start_downloading_pipeline(
-                Arc::new(das_client),
+            let heartbeats = asset_processing::start_downloading_pipeline(
+                das_client.clone(),
                 media_storag_client.clone(),
-                &app_cfg.das,
-                &app_cfg.asset_processor,
+                originals.clone(),
+                reloadable.das,
+                reloadable.asset_processor,
+                reloadable.url_filter,
             ).await;
+            health_checker.das_client = Some(das_client);
+            health_checker.pipeline = Some(heartbeats);
         }
         
-        app_metrics::run_sys_metrics_collector().await;
-
-        if app_cfg.http_server.enabled {
-            // Provides downloaded NFT assets via HTTP
-            http_endpoints::run_img_server(&app_cfg.http_server, media_storag_client.clone())
-                .await.unwrap();
+        if app_cfg.metrics.enabled {
+            let interval = Duration::from_secs(app_cfg.metrics.collection_interval_secs);
+            app_metrics::run_sys_metrics_collector(interval).await;
         }
 
+        let admin_server = async {
+            if app_cfg.admin_server.enabled {
+                admin_endpoints::run_admin_server(&app_cfg.admin_server, recorder_handle, Arc::new(health_checker), takedown).await
+            } else {
+                std::future::pending().await
+            }
+        };
+
+        let img_server = async {
+            if app_cfg.http_server.enabled {
+                // Provides downloaded NFT assets via HTTP
+                http_endpoints::run_img_server(&app_cfg.http_server, media_storag_client.clone(), originals.clone()).await
+            } else {
+                std::future::pending().await
+            }
+        };
+
+        // Servers run until one of them fails, otherwise we keep running for the sake of the pipeline
+        tokio::try_join!(admin_server, img_server)?;
+
+        Ok(())
     }
-}
\ No newline at end of file
+}
@@ -1,27 +1,37 @@
-use std::sync::Arc;
+use std::{future::Future, sync::Arc, time::Duration};
 
-use tokio::time::Instant;
+use tokio::{sync::watch, time::Instant};
+use tracing::{Instrument, Span};
 
 use crate::{
+    app_metrics::{
+        CAT_OPERATION, CAT_OUTCOME, CAT_RULE, MET_ASSET_PROCESSING_DURATION, MET_CONTENT_DEDUP, MET_DAS_REQUEST_DURATION,
+        MET_RESULTS_SUBMITTED, MET_URLS_BLOCKED
+    },
     configs::{AssetProcessorCfg, DasCfg},
     das_client::{DasClient, DlOutcome, UrlDlResult},
-    download::download,
-    image_resize::{self, ImgResizeError},
+    download::{download, DlError},
+    health::PipelineHeartbeats,
+    image_resize,
     media_type::AssetClass,
-    obj_storage_client::MediaStorageClient,
-    string_util::keccak256_hash_bs58str
+    media_store::{self, Denied, KeyKind, MediaMetadata, MediaStore, OriginalsArchive, StorageError, PROCESSING_VERSION},
+    string_util::{keccak256_hash_bs58, keccak256_hash_bs58str},
+    url_filter::UrlFilter,
 };
 
 const SEND_BACK_BUFFER_SIZE: usize = 100;
+/// Delay before the next poll, if the previous one has returned no URLs
+const EMPTY_BATCH_POLL_DELAY: Duration = Duration::from_secs(1);
 
 pub enum Task {
-    /// ID, URL
-    Download { url: String },
+    /// URL to download, and the span of the DAS request it has been fetched by
+    Download { url: String, fetch_span: Span },
     /// We use this to decrease the number of download workers in runtime if needed
     Finish
 }
 
-pub struct TaskResp(UrlDlResult);
+/// URL processing result, and the span of the processing
+pub struct TaskResp(UrlDlResult, Span);
 
 /// The whole processing schema looks as following:
 /// ```no-syntax
@@ -38,34 +48,98 @@ pub struct TaskResp(UrlDlResult);
 /// ```
 /// No need for graceful shutdown because, downloaded assets are persited in
 /// a idempotent way, i.e. at least once semantics is perfectly fine for us.
+///
+/// Asset processor settings, URL filter, batch size and number of workers are taken from the given
+/// watch channels, so that config changes are applied without restart.
+///
+/// Returns heartbeats of the poller and workers, that are used for liveness checks.
 pub async fn start_downloading_pipeline(
     das_client: Arc<dyn DasClient + Send + Sync + 'static>,
-    media_storage: Arc<MediaStorageClient>,
-    das_cfg: &DasCfg,
-    asset_cfg: &AssetProcessorCfg,
-) {
-    let tasks_queue_size = das_cfg.number_of_workers * das_cfg.fetch_batch_size as usize;
+    media_storage: Arc<dyn MediaStore + Send + Sync>,
+    originals: Option<Arc<OriginalsArchive>>,
+    das_cfg: watch::Receiver<DasCfg>,
+    asset_cfg: watch::Receiver<AssetProcessorCfg>,
+    url_filter: watch::Receiver<Arc<UrlFilter>>,
+) -> Arc<PipelineHeartbeats> {
+    let tasks_queue_size = {
+        let das_cfg = das_cfg.borrow();
+        das_cfg.number_of_workers * das_cfg.fetch_batch_size as usize
+    };
     let (resp_sender, resp_recv) = tokio::sync::mpsc::channel::<TaskResp>(tasks_queue_size);
     let (task_sender, task_recv) = async_channel::bounded::<Task>(tasks_queue_size);
+    let heartbeats = Arc::new(PipelineHeartbeats::new());
 
-    for _ in 0 .. das_cfg.number_of_workers {
-        make_worker(task_recv.clone(), resp_sender.clone(), media_storage.clone(), asset_cfg.clone()).await;    
-    }
+    let spawn_worker = {
+        let heartbeats = heartbeats.clone();
+        move || make_worker(
+            task_recv.clone(), resp_sender.clone(), media_storage.clone(), originals.clone(), asset_cfg.clone(), url_filter.clone(),
+            heartbeats.clone(),
+        )
+    };
+    make_workers_scaler(das_cfg.clone(), task_sender.clone(), spawn_worker).await;
 
-    make_poller(das_client.clone(), task_sender, das_cfg.fetch_batch_size).await;
+    make_poller(das_client.clone(), task_sender, das_cfg, heartbeats.clone()).await;
     make_results_sender(das_client.clone(),resp_recv).await;
+
+    heartbeats
+}
+
+/// Keeps the number of workers equal to the configured one
+async fn make_workers_scaler<F, Fut>(
+    mut das_cfg: watch::Receiver<DasCfg>,
+    task_sender: async_channel::Sender<Task>,
+    spawn_worker: F,
+) where
+    F: Fn() -> Fut + Send + 'static,
+    Fut: Future<Output = ()> + Send,
+{
+    let mut workers_number = das_cfg.borrow_and_update().number_of_workers;
+    for _ in 0 .. workers_number {
+        spawn_worker().await;
+    }
+
+    tokio::spawn(async move {
+        while das_cfg.changed().await.is_ok() {
+            let required_number = das_cfg.borrow_and_update().number_of_workers;
+            for _ in workers_number .. required_number {
+                spawn_worker().await;
+            }
+            // Extra workers finish after the tasks that are already in the queue
+            for _ in required_number .. workers_number {
+                if task_sender.send(Task::Finish).await.is_err() {
+                    return;
+                }
+            }
+            workers_number = required_number;
+        }
+    });
 }
 
 async fn make_poller(
     das_client: Arc<dyn DasClient + Send + Sync + 'static>,
     task_sender: async_channel::Sender<Task>,
-    poll_batch_size: u32,
+    das_cfg: watch::Receiver<DasCfg>,
+    heartbeats: Arc<PipelineHeartbeats>,
 ) {
     tokio::spawn(async move {
         loop {
-            let to_process = das_client.fetch_assets_for_downloading(poll_batch_size).await;
+            heartbeats.poller.beat();
+            let poll_batch_size = das_cfg.borrow().fetch_batch_size;
+            let start = Instant::now();
+            let fetch_span = tracing::info_span!("fetch_urls");
+            let to_process = das_client.fetch_assets_for_downloading(poll_batch_size)
+                .instrument(fetch_span.clone())
+                .await;
+            metrics::histogram!(MET_DAS_REQUEST_DURATION, CAT_OPERATION => "fetch_assets").record(start.elapsed().as_secs_f64());
+            if to_process.is_empty() {
+                // Either nothing to download, or DAS node is unavailable, no need to hammer it
+                tokio::time::sleep(EMPTY_BATCH_POLL_DELAY).await;
+                continue;
+            }
             for asset in to_process {
-                task_sender.send(Task::Download { url: asset })
+                let task = Task::Download { url: asset, fetch_span: fetch_span.clone() };
+                // Waiting for a free slot in the queue while workers are busy is not a hang
+                heartbeats.poller.beat_while(task_sender.send(task))
                     .await.unwrap();
             }
         }
@@ -75,88 +149,340 @@ async fn make_poller(
 async fn make_results_sender(das_client: Arc<dyn DasClient + Send + Sync + 'static>, mut resp_recv: tokio::sync::mpsc::Receiver<TaskResp>) {
     tokio::spawn(async move {
         let mut buffer: Vec<UrlDlResult> = Vec::new(); // NFT Id -> mime type
-        let mut start = Instant::now();
-        loop {
-            match resp_recv.recv().await {
-                Some(TaskResp(asset_download_result)) => {
-                    buffer.push(asset_download_result);
+        let mut submit_span = tracing::info_span!("submit_results");
+        while let Some(TaskResp(asset_download_result, processing_span)) = resp_recv.recv().await {
+            buffer.push(asset_download_result);
+            submit_span.follows_from(&processing_span);
 
-                    if buffer.len() >= SEND_BACK_BUFFER_SIZE {
-                        let latency = start.elapsed().as_secs_f64();
-                        metrics::gauge!("flow_rate").set(latency / SEND_BACK_BUFFER_SIZE as f64);
-                        start = Instant::now();
-
-                        das_client.notify_finished(buffer).await;
-                        buffer = Vec::new();
-                    }
-                },
-                None => break,
+            if buffer.len() >= SEND_BACK_BUFFER_SIZE {
+                notify_finished(das_client.as_ref(), buffer).instrument(submit_span).await;
+                buffer = Vec::new();
+                submit_span = tracing::info_span!("submit_results");
             }
         }
         if !buffer.is_empty() {
-            das_client.notify_finished(buffer).await;
+            notify_finished(das_client.as_ref(), buffer).instrument(submit_span).await;
         }
     });
 }
 
+async fn notify_finished(das_client: &(dyn DasClient + Send + Sync), results: Vec<UrlDlResult>) {
+    let start = Instant::now();
+    let results_number = results.len() as u64;
+    das_client.notify_finished(results).await;
+    metrics::histogram!(MET_DAS_REQUEST_DURATION, CAT_OPERATION => "notify_finished").record(start.elapsed().as_secs_f64());
+    metrics::counter!(MET_RESULTS_SUBMITTED).increment(results_number);
+}
+
 async fn make_worker(
     requests: async_channel::Receiver<Task>,
     responses: tokio::sync::mpsc::Sender<TaskResp>,
-    media_storage: Arc<MediaStorageClient>,
-    asset_cfg: AssetProcessorCfg,
+    media_storage: Arc<dyn MediaStore + Send + Sync>,
+    originals: Option<Arc<OriginalsArchive>>,
+    asset_cfg: watch::Receiver<AssetProcessorCfg>,
+    url_filter: watch::Receiver<Arc<UrlFilter>>,
+    heartbeats: Arc<PipelineHeartbeats>,
 ) {
     tokio::spawn(async move {
         metrics::gauge!("workers_count").increment(1);
-        loop {
-            match requests.recv().await {
-                Ok(msg) => {
-                    match msg {
-                        Task::Download { url} => {
-                            let asset_download_result = process_url(url, &media_storage, &asset_cfg).await;
-                            match responses.send(TaskResp(asset_download_result)).await {
-                                Ok(_) => (),
-                                Err(_) => break,
-                            }
-                        },
-                        Task::Finish => break,
+        let heartbeat = heartbeats.register_worker();
+        while let Ok(msg) = heartbeat.beat_while(requests.recv()).await {
+            match msg {
+                Task::Download { url, fetch_span } => {
+                    // Each URL is processed in its own trace, linked to the DAS request it came from
+                    let processing_span = tracing::info_span!(parent: None, "process_url", url_hash = tracing::field::Empty);
+                    processing_span.follows_from(&fetch_span);
+                    let asset_cfg = asset_cfg.borrow().clone();
+                    let url_filter = url_filter.borrow().clone();
+                    let asset_download_result = process_url(url, media_storage.as_ref(), originals.as_deref(), &asset_cfg, &url_filter)
+                        .instrument(processing_span.clone())
+                        .await;
+                    match responses.send(TaskResp(asset_download_result, processing_span)).await {
+                        Ok(_) => (),
+                        Err(_) => break,
                     }
                 },
-                Err(_) => break,
+                Task::Finish => break,
             }
         }
+        heartbeats.unregister_worker(&heartbeat);
         metrics::gauge!("workers_count").decrement(1);
     });
+}
 
-    async fn process_url(url: String, media_storage: &MediaStorageClient, asset_cfg: &AssetProcessorCfg) -> UrlDlResult {
-        let start = Instant::now();
+/// Processes a single URL from the DAS node: downloads it, makes a preview and stores it
+pub async fn process_url(
+    url: String,
+    media_storage: &(dyn MediaStore + Send + Sync),
+    originals: Option<&OriginalsArchive>,
+    asset_cfg: &AssetProcessorCfg,
+    url_filter: &Arc<UrlFilter>,
+) -> UrlDlResult {
+    let start = Instant::now();
 
-        let id = keccak256_hash_bs58str(&url);
-        let asset_download_result = match download(&url, asset_cfg.file_max_size_bytes).await {
-            Ok((bytes, mime)) => {
-                if mime.class == AssetClass::Image {
-                    match image_resize::resize_fast(&bytes, asset_cfg.resize_to) {
-                        Ok(resized) => {
-                            media_storage.save_media(&id, resized.into(),mime.str()).await.unwrap();
-                            UrlDlResult { url, outcome: DlOutcome::success(mime.str(), asset_cfg.resize_to) }
-                        },
-                        Err(ImgResizeError::NoResizeNeeded) => {
-                            media_storage.save_media(&id, bytes.into(),mime.str()).await.unwrap();
-                            UrlDlResult { url, outcome: DlOutcome::success(mime.str(), asset_cfg.resize_to) }       
-                        },
-                        Err(err) =>
-                            UrlDlResult { url, outcome: DlOutcome::corrupted_asset(err.to_string()) }
-                    }
-                } else {
-                    UrlDlResult { url, outcome: DlOutcome::unsupported_format(mime.str()) }
-                }
-            } ,
-            Err(err) => {
-                UrlDlResult { url, outcome: err.into() }
-            },
+    let id = keccak256_hash_bs58str(&url);
+    Span::current().record("url_hash", &id);
+
+    // Blocked URLs are not looked up in the storage at all, even if they have been processed before the rule was added
+    let blocking_rule = url_filter.blocking_rule(&url);
+    let denied = match blocking_rule {
+        Some(_) => Ok(false),
+        None => is_denied(Denied::Url, &id, media_storage).await,
+    };
+    let already_processed = if blocking_rule.is_some() || !matches!(denied, Ok(false)) || asset_cfg.force_reprocess {
+        None
+    } else {
+        find_processed(KeyKind::Media, &id, media_storage).await
+    };
+    let skipped = already_processed.is_some();
+    let asset_download_result = match (blocking_rule, denied, already_processed) {
+        (Some(rule), _, _) => UrlDlResult { url, outcome: DlOutcome::blocked(rule) },
+        (None, Err(err), _) => UrlDlResult { url, outcome: err.into() },
+        (None, Ok(true), _) => UrlDlResult { url, outcome: DlOutcome::taken_down() },
+        (None, Ok(false), Some(metadata)) =>
+            UrlDlResult { url, outcome: DlOutcome::success(&metadata.original_mime, asset_cfg.resize_to) },
+        (None, Ok(false), None) => download_and_store(url, &id, media_storage, originals, asset_cfg, url_filter.clone()).await,
+    };
+    // Either the URL itself or a redirect from it
+    if let DlOutcome::Fail { err: DlError::Blocked(rule) } = &asset_download_result.outcome {
+        metrics::counter!(MET_URLS_BLOCKED, CAT_RULE => rule.clone()).increment(1);
+    }
+
+    let outcome = match &asset_download_result.outcome {
+        DlOutcome::Success { .. } if skipped => "already_processed",
+        DlOutcome::Success { .. } => "success",
+        DlOutcome::Fail { err } => err.metric_label(),
+    };
+    let duration = start.elapsed();
+    metrics::histogram!(MET_ASSET_PROCESSING_DURATION, CAT_OUTCOME => outcome).record(duration.as_secs_f64());
+    tracing::info!(
+        url_hash = %id,
+        host = url_host(&asset_download_result.url).unwrap_or_default(),
+        outcome,
+        details = match &asset_download_result.outcome {
+            DlOutcome::Fail { err } => err.to_string(),
+            DlOutcome::Success { .. } => String::new(),
+        },
+        duration_ms = duration.as_millis() as u64,
+        "URL processed"
+    );
+
+    asset_download_result
+}
+
+async fn download_and_store(
+    url: String,
+    id: &str,
+    media_storage: &(dyn MediaStore + Send + Sync),
+    originals: Option<&OriginalsArchive>,
+    asset_cfg: &AssetProcessorCfg,
+    url_filter: Arc<UrlFilter>,
+) -> UrlDlResult {
+    let downloaded = download(&url, asset_cfg.file_max_size_bytes, url_filter)
+        .instrument(tracing::info_span!("download"))
+        .await;
+    let (bytes, mime) = match downloaded {
+        Ok(downloaded) => downloaded,
+        Err(err) => return UrlDlResult { url, outcome: err.into() },
+    };
+    let content_hash = keccak256_hash_bs58(&bytes);
+    match is_denied(Denied::Content, &content_hash, media_storage).await {
+        Ok(false) => (),
+        Ok(true) => {
+            // Same content under a new URL, there is no need to download it ever again
+            let reason = format!("Same content as a taken down asset ({content_hash})");
+            if let Err(err) = media_store::deny(media_storage, Denied::Url, id, &reason, unix_timestamp()).await {
+                tracing::warn!(url_hash = %id, error = %err, "Cannot add URL to the denylist");
+            }
+            return UrlDlResult { url, outcome: DlOutcome::taken_down() };
+        },
+        Err(err) => return UrlDlResult { url, outcome: err.into() },
+    }
+    if let Some(originals) = originals {
+        originals.archive(id, &url, &bytes, &mime).instrument(tracing::info_span!("archive_original")).await;
+    }
+    if mime.class != AssetClass::Image {
+        return UrlDlResult { url, outcome: DlOutcome::unsupported_format(mime.str()) };
+    }
+
+    let content_hash = asset_cfg.deduplicate.then_some(content_hash);
+    if let Some(content_hash) = &content_hash {
+        let existing = if asset_cfg.force_reprocess {
+            None
+        } else {
+            find_processed(KeyKind::Content, content_hash, media_storage).await
         };
+        metrics::counter!(MET_CONTENT_DEDUP, CAT_OUTCOME => if existing.is_some() { "duplicate" } else { "unique" }).increment(1);
+        if let Some(existing) = existing {
+            // Same bytes give the same preview, only the asset-specific part differs
+            let metadata = MediaMetadata {
+                original_url: url.clone(),
+                original_mime: mime.str().to_string(),
+                original_size: bytes.len() as u64,
+                processed_at: unix_timestamp(),
+                ..existing
+            };
+            let outcome = match media_storage.save_alias(id, content_hash, &metadata).await {
+                Ok(()) => DlOutcome::success(mime.str(), asset_cfg.resize_to),
+                Err(err) => DlError::from(err).into(),
+            };
+            return UrlDlResult { url, outcome };
+        }
+    }
+
+    let preview = tracing::info_span!("resize")
+        .in_scope(|| image_resize::make_preview(&bytes, asset_cfg.resize_to));
+    match preview {
+        Ok(preview) => {
+            let metadata = MediaMetadata {
+                original_url: url.clone(),
+                original_mime: mime.str().to_string(),
+                original_size: bytes.len() as u64,
+                original_width: Some(preview.original_width),
+                original_height: Some(preview.original_height),
+                preview_width: Some(preview.width),
+                preview_height: Some(preview.height),
+                encoder: preview.encoder.to_string(),
+                processing_version: PROCESSING_VERSION,
+                processed_at: unix_timestamp(),
+            };
+            // Content stored without its alias is picked up by the dedup when the URL is processed again
+            let stored = match &content_hash {
+                Some(content_hash) => async {
+                    media_storage.save_content(content_hash, preview.bytes.into(), image_resize::PREVIEW_MIME, &metadata).await?;
+                    media_storage.save_alias(id, content_hash, &metadata).await
+                }.await,
+                None => media_storage.save_media(id, preview.bytes.into(), image_resize::PREVIEW_MIME, &metadata).await,
+            };
+            let outcome = match stored {
+                Ok(()) => DlOutcome::success(mime.str(), asset_cfg.resize_to),
+                Err(err) => DlError::from(err).into(),
+            };
+            UrlDlResult { url, outcome }
+        },
+        Err(err) =>
+            UrlDlResult { url, outcome: DlOutcome::corrupted_asset(err.to_string()) }
+    }
+}
+
+/// Returns metadata of the preview (or an alias of it) stored under any of the read keys,
+/// so that the previous key layout is looked at until it's migrated from,
+/// if it has been produced by the current processing version.
+/// An alias counts only if its target exists, the target may have been taken down.
+/// Storage failures are not fatal here, the asset is just processed again.
+async fn find_processed(kind: KeyKind, id: &str, media_storage: &(dyn MediaStore + Send + Sync)) -> Option<MediaMetadata> {
+    let lookup = async {
+        match kind {
+            KeyKind::Media => media_storage.head_media(id).await,
+            KeyKind::Content => media_storage.head_first(&media_storage.key_layout().read_keys(kind, id)).await,
+        }
+    };
+    match lookup.instrument(tracing::info_span!("check_processed")).await {
+        Ok(info) => MediaMetadata::from_object_metadata(&info.metadata)
+            .filter(|metadata| metadata.processing_version >= PROCESSING_VERSION),
+        Err(StorageError::NotFound) => None,
+        Err(err) => {
+            tracing::warn!(id, error = %err, "Cannot check whether the asset has been processed already");
+            None
+        },
+    }
+}
+
+/// Storage failures fail the URL, so that DAS node retries it later, instead of a taken down asset being restored
+async fn is_denied(denied: Denied, hash: &str, media_storage: &(dyn MediaStore + Send + Sync)) -> Result<bool, DlError> {
+    let result = media_store::is_denied(media_storage, denied, hash).instrument(tracing::info_span!("check_denylist")).await;
+    Ok(result?)
+}
+
+fn url_host(url: &str) -> Option<String> {
+    reqwest::Url::parse(url).ok()?.host_str().map(str::to_string)
+}
+
+pub fn unix_timestamp() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default().as_secs()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bytes::Bytes;
+    use crate::{
+        configs::{KeyLayoutCfg, MediaStoreCfg},
+        download::serve_test_image,
+        media_store::{test_metadata, KeyLayout, MemoryMediaStore, ReadOnlyStore, UnavailableStore},
+    };
 
-        metrics::histogram!("asset_processing").record(start.elapsed().as_secs_f64());
+    const URL: &str = "https://example.com/1.png";
+
+    #[tokio::test]
+    async fn test_find_processed() {
+        let store = MemoryMediaStore::default();
+        store.save_media("current", bytes::Bytes::from_static(b"a").into(), "image/webp", &test_metadata(URL, PROCESSING_VERSION)).await.unwrap();
+        store.save_media("outdated", bytes::Bytes::from_static(b"a").into(), "image/webp", &test_metadata(URL, PROCESSING_VERSION - 1)).await.unwrap();
+        store.put("media/legacy", bytes::Bytes::from_static(b"a").into(), "image/png", &Default::default()).await.unwrap();
+
+        store.save_content("hash1", bytes::Bytes::from_static(b"a").into(), "image/webp", &test_metadata(URL, PROCESSING_VERSION)).await.unwrap();
+        store.save_alias("alias", "hash1", &test_metadata(URL, PROCESSING_VERSION)).await.unwrap();
+        store.save_alias("dangling", "missing", &test_metadata(URL, PROCESSING_VERSION)).await.unwrap();
+
+        assert_eq!(find_processed(KeyKind::Media, "current", &store).await, Some(test_metadata(URL, PROCESSING_VERSION)));
+        assert_eq!(find_processed(KeyKind::Media, "outdated", &store).await, None);
+        assert_eq!(find_processed(KeyKind::Media, "legacy", &store).await, None);
+        assert_eq!(find_processed(KeyKind::Media, "missing", &store).await, None);
+        assert_eq!(find_processed(KeyKind::Media, "alias", &store).await, Some(test_metadata(URL, PROCESSING_VERSION)));
+        assert_eq!(find_processed(KeyKind::Media, "dangling", &store).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_previous_layout_is_looked_up() {
+        let cfg = MediaStoreCfg {
+            key_layout: KeyLayoutCfg { shard_levels: 1, ..Default::default() },
+            previous_key_layout: Some(KeyLayoutCfg::default()),
+            ..Default::default()
+        };
+        let store = MemoryMediaStore::new(KeyLayout::new(&cfg));
+        let metadata = test_metadata(URL, PROCESSING_VERSION).to_object_metadata();
+        store.put("media/asset1", Bytes::from_static(b"a").into(), "image/webp", &metadata).await.unwrap();
+        store.put("content/hash1", Bytes::from_static(b"a").into(), "image/webp", &metadata).await.unwrap();
+
+        assert!(find_processed(KeyKind::Media, "asset1", &store).await.is_some());
+        assert!(find_processed(KeyKind::Content, "hash1", &store).await.is_some());
+    }
+
+    fn asset_cfg() -> AssetProcessorCfg {
+        AssetProcessorCfg { resize_to: 400, file_max_size_bytes: 1024 * 1024, force_reprocess: false, deduplicate: true }
+    }
+
+    #[tokio::test]
+    async fn test_url_is_processed() {
+        let store = MemoryMediaStore::default();
+        let url = format!("{}/1.png", serve_test_image().await);
+
+        let result = process_url(url.clone(), &store, None, &asset_cfg(), &Arc::default()).await;
+        assert!(matches!(result.outcome, DlOutcome::Success { ref mime, size: 400 } if mime == "image/png"));
+
+        let id = keccak256_hash_bs58str(&url);
+        assert_eq!(store.get_media(&id).await.unwrap().mime, image_resize::PREVIEW_MIME);
+        let metadata = MediaMetadata::from_object_metadata(&store.head_media(&id).await.unwrap().metadata).unwrap();
+        assert_eq!(metadata.original_url, url);
+        assert_eq!(metadata.processing_version, PROCESSING_VERSION);
+    }
+
+    #[tokio::test]
+    async fn test_storage_failure_is_reported() {
+        let url = format!("{}/1.png", serve_test_image().await);
+        for deduplicate in [true, false] {
+            let asset_cfg = AssetProcessorCfg { deduplicate, ..asset_cfg() };
+            let result = process_url(url.clone(), &ReadOnlyStore::default(), None, &asset_cfg, &Arc::default()).await;
+            assert!(matches!(result.outcome, DlOutcome::Fail { err: DlError::StorageError(StorageError::Forbidden) }));
+        }
+    }
 
-        asset_download_result
+    #[tokio::test]
+    async fn test_denylist_failure_is_reported() {
+        // The URL might have been taken down, so it's not processed
+        let url = format!("{}/1.png", serve_test_image().await);
+        let result = process_url(url, &UnavailableStore::default(), None, &asset_cfg(), &Arc::default()).await;
+        assert!(matches!(result.outcome, DlOutcome::Fail { err: DlError::StorageError(StorageError::Unavailable(_)) }));
     }
 }
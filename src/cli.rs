@@ -0,0 +1,72 @@
+//! Command line interface of the application binary.
+use clap::{ArgGroup, Parser, Subcommand};
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Downloads, resizes and serves NFT media files")]
+pub struct Cli {
+    /// Directory containing `default.toml` and the profile config files
+    #[arg(long, global = true, env = "RUN_CONFIG_DIR")]
+    pub config_dir: Option<String>,
+
+    /// Config profile, i.e. name of the config file (without extension) that is merged over `default.toml`
+    #[arg(long, global = true, env = "RUN_ENV")]
+    pub env: Option<String>,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// Run all the components enabled in config (default)
+    Serve,
+    /// Run only the downloading pipeline and the admin server, without the preview HTTP server
+    Worker,
+    /// Load the config, print it and exit
+    CheckConfig,
+    /// Move stored objects from `media_store.previous_key_layout` to `media_store.key_layout`
+    MigrateLayout {
+        /// Delete the objects from the previous layout once they are copied
+        #[arg(long)]
+        delete_source: bool,
+        /// Only log what would be migrated
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Delete the asset preview and add its URL and content to the denylist
+    #[command(group(ArgGroup::new("asset").required(true).args(["id", "url"])))]
+    Takedown {
+        /// Asset ID, i.e. the URL hash
+        #[arg(long)]
+        id: Option<String>,
+        #[arg(long)]
+        url: Option<String>,
+        /// Why the asset is taken down, kept in the denylist
+        #[arg(long)]
+        reason: String,
+    },
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_serve_is_default_command() {
+        let cli = Cli::try_parse_from(["media-files-store", "--env", "prod"]).unwrap();
+        assert_eq!(cli.command, None);
+        assert_eq!(cli.env.as_deref(), Some("prod"));
+
+        let cli = Cli::try_parse_from(["media-files-store", "check-config", "--config-dir", "/etc/mfs"]).unwrap();
+        assert_eq!(cli.command, Some(Command::CheckConfig));
+        assert_eq!(cli.config_dir.as_deref(), Some("/etc/mfs"));
+
+        let cli = Cli::try_parse_from(["media-files-store", "migrate-layout", "--dry-run"]).unwrap();
+        assert_eq!(cli.command, Some(Command::MigrateLayout { delete_source: false, dry_run: true }));
+
+        let cli = Cli::try_parse_from(["media-files-store", "takedown", "--url", "https://a/1.png", "--reason", "DMCA"]).unwrap();
+        assert_eq!(cli.command, Some(Command::Takedown { id: None, url: Some("https://a/1.png".to_string()), reason: "DMCA".to_string() }));
+        assert!(Cli::try_parse_from(["media-files-store", "takedown", "--reason", "DMCA"]).is_err());
+        assert!(Cli::try_parse_from(["media-files-store", "takedown", "--id", "a", "--url", "b", "--reason", "DMCA"]).is_err());
+    }
+}
@@ -0,0 +1,230 @@
+//! Applying config changes to the running application.
+//!
+//! Config is reloaded on SIGHUP, or when a file in the config directory changes.
+//! Only the downloading pipeline settings (`asset_processor`, `url_filter` and some of `das`) are applied
+//! to the running components, changes of other settings are logged as requiring a restart.
+use std::{path::Path, sync::Arc, time::{Duration, SystemTime}};
+
+use anyhow::Context;
+use tokio::sync::watch;
+
+use crate::{
+    configs::{AssetProcessorCfg, ConfigSource, DasCfg, Settings},
+    url_filter::UrlFilter,
+};
+
+/// Settings that are applied without restart
+pub struct ReloadableSettings {
+    pub asset_processor: watch::Receiver<AssetProcessorCfg>,
+    pub das: watch::Receiver<DasCfg>,
+    /// Compiled on reload, so that workers don't have to
+    pub url_filter: watch::Receiver<Arc<UrlFilter>>,
+}
+
+/// Changed config value
+#[derive(Debug, PartialEq, Eq)]
+pub struct ConfigChange {
+    pub path: &'static str,
+    pub old: String,
+    pub new: String,
+    /// Whether the change is applied without restart
+    pub reloadable: bool,
+}
+
+/// Starts watching for config changes.
+/// `current` is the config loaded on the startup, it's what the reloaded config is compared with.
+/// Fails if the URL filter cannot be compiled, in case the config has not been validated.
+pub fn spawn_config_reloader(source: ConfigSource, current: Settings) -> anyhow::Result<ReloadableSettings> {
+    let (asset_processor_sender, asset_processor) = watch::channel(current.asset_processor.clone());
+    let (das_sender, das) = watch::channel(current.das.clone());
+    let url_filter = UrlFilter::new(&current.url_filter).context("Invalid url_filter pattern")?;
+    let (url_filter_sender, url_filter) = watch::channel(Arc::new(url_filter));
+
+    tokio::spawn(async move {
+        let mut current = current;
+        let mut reload_triggers = ReloadTriggers::new(&source, &current);
+        while let Some(trigger) = reload_triggers.next().await {
+            let new = match source.load() {
+                Ok(new) => new,
+                Err(e) => {
+                    tracing::error!(trigger, error = %e, "Cannot reload config, keeping the current one");
+                    continue;
+                }
+            };
+
+            let changes = config_changes(&current, &new);
+            if changes.is_empty() {
+                tracing::info!(trigger, "Config reloaded, nothing has changed");
+                continue;
+            }
+            for change in &changes {
+                if change.reloadable {
+                    tracing::info!(setting = change.path, old = %change.old, new = %change.new, "Config setting changed");
+                } else {
+                    tracing::warn!(setting = change.path, old = %change.old, new = %change.new,
+                        "Config setting changed, restart is required to apply it");
+                }
+            }
+
+            asset_processor_sender.send_if_modified(|cfg| replace_if_changed(cfg, &new.asset_processor));
+            // DAS settings that require restart stay as they were at the startup
+            let das_update = DasCfg {
+                fetch_batch_size: new.das.fetch_batch_size,
+                number_of_workers: new.das.number_of_workers,
+                ..das_sender.borrow().clone()
+            };
+            das_sender.send_if_modified(|cfg| replace_if_changed(cfg, &das_update));
+            if new.url_filter != current.url_filter {
+                match UrlFilter::new(&new.url_filter) {
+                    Ok(filter) => { url_filter_sender.send_replace(Arc::new(filter)); },
+                    Err(e) => tracing::error!(error = %e, "Cannot apply the URL filter, keeping the current one"),
+                }
+            }
+
+            current = new;
+        }
+    });
+
+    Ok(ReloadableSettings { asset_processor, das, url_filter })
+}
+
+fn replace_if_changed<T: PartialEq + Clone>(current: &mut T, new: &T) -> bool {
+    if current != new {
+        *current = new.clone();
+        true
+    } else {
+        false
+    }
+}
+
+/// Lists the config values that differ, settings that require restart are compared by whole sections
+pub fn config_changes(old: &Settings, new: &Settings) -> Vec<ConfigChange> {
+    let mut changes = Vec::new();
+    let mut compare = |path: &'static str, old: String, new: String, reloadable: bool| {
+        if old != new {
+            changes.push(ConfigChange { path, old, new, reloadable });
+        }
+    };
+
+    compare("asset_processor.resize_to", old.asset_processor.resize_to.to_string(), new.asset_processor.resize_to.to_string(), true);
+    compare("asset_processor.file_max_size_bytes", old.asset_processor.file_max_size_bytes.to_string(), new.asset_processor.file_max_size_bytes.to_string(), true);
+    compare("asset_processor.force_reprocess", old.asset_processor.force_reprocess.to_string(), new.asset_processor.force_reprocess.to_string(), true);
+    compare("asset_processor.deduplicate", old.asset_processor.deduplicate.to_string(), new.asset_processor.deduplicate.to_string(), true);
+    compare("das.fetch_batch_size", old.das.fetch_batch_size.to_string(), new.das.fetch_batch_size.to_string(), true);
+    compare("das.number_of_workers", old.das.number_of_workers.to_string(), new.das.number_of_workers.to_string(), true);
+    compare("url_filter", format!("{:?}", old.url_filter), format!("{:?}", new.url_filter), true);
+
+    compare("das.enabled", old.das.enabled.to_string(), new.das.enabled.to_string(), false);
+    compare("das.grpc_address", old.das.grpc_address.clone(), new.das.grpc_address.clone(), false);
+    compare("http_server", format!("{:?}", old.http_server), format!("{:?}", new.http_server), false);
+    compare("admin_server", format!("{:?}", old.admin_server), format!("{:?}", new.admin_server), false);
+    compare("media_store", format!("{:?}", old.media_store), format!("{:?}", new.media_store), false);
+    compare("obj_storage", format!("{:?}", old.obj_storage), format!("{:?}", new.obj_storage), false);
+    compare("obj_storage_replicas", format!("{:?}", old.obj_storage_replicas), format!("{:?}", new.obj_storage_replicas), false);
+    compare("originals", format!("{:?}", old.originals), format!("{:?}", new.originals), false);
+    compare("health", format!("{:?}", old.health), format!("{:?}", new.health), false);
+    compare("metrics", format!("{:?}", old.metrics), format!("{:?}", new.metrics), false);
+    compare("logging", format!("{:?}", old.logging), format!("{:?}", new.logging), false);
+    compare("tracing", format!("{:?}", old.tracing), format!("{:?}", new.tracing), false);
+    compare("config_reload", format!("{:?}", old.config_reload), format!("{:?}", new.config_reload), false);
+
+    changes
+}
+
+/// Sources of the reload events: SIGHUP and modifications of the config files
+struct ReloadTriggers {
+    #[cfg(unix)]
+    sighup: Option<tokio::signal::unix::Signal>,
+    /// Config directory and the time of its last observed modification
+    watched_dir: Option<(String, Option<SystemTime>)>,
+    poll_interval: Duration,
+}
+
+impl ReloadTriggers {
+    fn new(source: &ConfigSource, settings: &Settings) -> ReloadTriggers {
+        #[cfg(unix)]
+        let sighup = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup()) {
+            Ok(signal) => Some(signal),
+            Err(e) => {
+                tracing::warn!(error = %e, "Cannot listen for SIGHUP, config reload on signal is disabled");
+                None
+            }
+        };
+        let watched_dir = settings.config_reload.watch_files
+            .then(|| (source.config_dir.clone(), last_modified(Path::new(&source.config_dir))));
+        ReloadTriggers {
+            #[cfg(unix)]
+            sighup,
+            watched_dir,
+            poll_interval: Duration::from_secs(settings.config_reload.poll_interval_secs),
+        }
+    }
+
+    /// Waits for the next reload event and returns its name
+    async fn next(&mut self) -> Option<&'static str> {
+        #[cfg(unix)]
+        let sighup_signal = self.sighup.as_mut();
+        #[cfg(unix)]
+        let sighup = async move {
+            match sighup_signal {
+                Some(signal) => signal.recv().await,
+                None => std::future::pending().await,
+            }
+        };
+        #[cfg(not(unix))]
+        let sighup = std::future::pending::<Option<()>>();
+
+        let poll_interval = self.poll_interval;
+        let watched_dir = self.watched_dir.as_mut();
+        let files_changed = async move {
+            let Some((dir, last_seen)) = watched_dir else {
+                return std::future::pending().await;
+            };
+            loop {
+                tokio::time::sleep(poll_interval).await;
+                let modified = last_modified(Path::new(dir));
+                if modified != *last_seen {
+                    *last_seen = modified;
+                    return;
+                }
+            }
+        };
+
+        tokio::select! {
+            signal = sighup => signal.map(|_| "sighup"),
+            _ = files_changed => Some("file_changed"),
+        }
+    }
+}
+
+/// The latest modification time of the files in the config directory
+fn last_modified(dir: &Path) -> Option<SystemTime> {
+    std::fs::read_dir(dir).ok()?
+        .filter_map(|entry| entry.ok()?.metadata().ok()?.modified().ok())
+        .max()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_config_changes() {
+        let old = ConfigSource::new(Some("local"), Some("./config")).load().unwrap();
+        let mut new = old.clone();
+        assert!(config_changes(&old, &new).is_empty());
+
+        new.asset_processor.resize_to = 800;
+        new.das.number_of_workers = 3;
+        new.http_server.port = 8090;
+        new.url_filter.deny_hosts.push("junk.com".to_string());
+
+        let changes = config_changes(&old, &new);
+        let summary: Vec<_> = changes.iter().map(|c| (c.path, c.new.as_str(), c.reloadable)).collect();
+        assert_eq!(summary.len(), 4);
+        assert_eq!(summary[0], ("asset_processor.resize_to", "800", true));
+        assert_eq!(summary[1], ("das.number_of_workers", "3", true));
+        assert_eq!((summary[2].0, summary[2].2), ("url_filter", true));
+        assert_eq!((summary[3].0, summary[3].2), ("http_server", false));
+    }
+}
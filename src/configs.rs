@@ -4,7 +4,7 @@
 //! TOML file in `config` directory.
 use config::{Config, ConfigError, Environment, File};
 use serde::Deserialize;
-use std::fmt;
+use std::{collections::HashMap, fmt, net::SocketAddr, time::Duration};
 use crate::string_util::StrUtil;
 
 const DEFAULT_CONFIG_FILE_PREFIX: &str = "./config";
@@ -17,10 +17,64 @@ fn default_config_file_path(base_path: &str) -> String {
 #[derive(Debug, Deserialize, Clone)]
 pub struct HttpServer {
     pub enabled: bool,
-    pub port: u16
+    pub port: u16,
+    /// How `/preview/:id` serves stored previews
+    #[serde(default)]
+    pub preview_mode: PreviewMode,
+    /// Lifetime of presigned S3 URLs issued in the redirect mode
+    #[serde(default = "default_presigned_url_ttl_secs")]
+    pub presigned_url_ttl_secs: u64,
+    /// If set, the redirect mode points clients to this base URL
+    /// (followed by the object key) instead of presigned S3 URLs
+    pub cdn_base_url: Option<String>,
+    /// Maximum time a request may be processed before 408 response is returned
+    #[serde(default = "default_request_timeout_secs")]
+    pub request_timeout_secs: u64,
+    #[serde(default = "default_max_request_body_bytes")]
+    pub max_request_body_bytes: usize,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum PreviewMode {
+    /// Preview bytes are streamed through the service
+    #[default]
+    Proxy,
+    /// Client is redirected to the object storage (or CDN),
+    /// only resized previews are streamed through the service
+    Redirect,
+}
+
+fn default_presigned_url_ttl_secs() -> u64 {
+    300
+}
+
+fn default_request_timeout_secs() -> u64 {
+    30
+}
+
+fn default_max_request_body_bytes() -> usize {
+    64 * 1024
+}
+
+/// Listener for internal endpoints: metrics, health checks and administration
+#[derive(Deserialize, Clone)]
+pub struct AdminServer {
+    pub enabled: bool,
+    pub bind_address: String,
+    pub port: u16,
+    #[serde(default = "default_request_timeout_secs")]
+    pub request_timeout_secs: u64,
+    #[serde(default = "default_max_request_body_bytes")]
+    pub max_request_body_bytes: usize,
+    /// Bearer token of the administration endpoints (e.g. takedown), they are disabled if not set
+    pub api_token: Option<String>,
+}
+
+/// Admin API token has to be hard to guess
+const MIN_API_TOKEN_LEN: usize = 32;
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
 pub struct DasCfg {
     pub enabled: bool,
     pub grpc_address: String,
@@ -32,16 +86,351 @@ pub struct DasCfg {
 pub struct ObjStorage {
     pub region: Option<String>,
     pub endpoint: Option<String>,
+    /// Where S3 credentials are taken from. If not set, static credentials are used
+    /// when they are specified, and the AWS default credentials chain otherwise.
+    pub credentials: Option<CredentialsSource>,
     pub access_key_id: Option<String>,
     pub secret_access_key: Option<String>,
     pub session_token: Option<String>,
+    /// Files containing static credentials, e.g. mounted Kubernetes secrets.
+    /// Files are re-read periodically, so rotated secrets are picked up without restart.
+    pub access_key_id_file: Option<String>,
+    pub secret_access_key_file: Option<String>,
+    pub session_token_file: Option<String>,
+    /// How often credentials are re-read from the files
+    #[serde(default = "default_credentials_refresh_secs")]
+    pub credentials_refresh_secs: u64,
+    /// AWS profile for the `profile` credentials, `AWS_PROFILE` or "default" if not set
+    pub profile_name: Option<String>,
+    /// Role and token file for the `web_identity` credentials,
+    /// if not set, they are taken from `AWS_ROLE_ARN` and `AWS_WEB_IDENTITY_TOKEN_FILE` environment variables
+    pub role_arn: Option<String>,
+    pub web_identity_token_file: Option<String>,
     pub bucket_for_media: String,
+    /// Creates the buckets at startup if they don't exist, for MinIO and development setups
+    #[serde(default)]
+    pub create_bucket_if_missing: bool,
+    #[serde(default)]
+    pub multipart: MultipartUploadCfg,
+}
+
+/// Objects bigger than the threshold are uploaded in parts, that are sent concurrently
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct MultipartUploadCfg {
+    pub threshold_bytes: u64,
+    /// S3 requires parts to be at least 5 MiB (except the last one), and allows up to 10000 parts
+    pub part_size_bytes: u64,
+    /// Maximum number of parts being uploaded at the same time
+    pub concurrency: usize,
+}
+
+impl Default for MultipartUploadCfg {
+    fn default() -> Self {
+        MultipartUploadCfg { threshold_bytes: 16 * MIB, part_size_bytes: 8 * MIB, concurrency: 4 }
+    }
+}
+
+const MIB: u64 = 1024 * 1024;
+/// S3 limits for multipart uploads
+const MULTIPART_MIN_PART_SIZE: u64 = 5 * MIB;
+const MULTIPART_MAX_PART_SIZE: u64 = 5 * 1024 * MIB;
+const MULTIPART_MAX_PARTS: u64 = 10_000;
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CredentialsSource {
+    /// Access keys from the config or from the `*_file` files
+    Static,
+    /// AWS default chain: environment variables, profile, web identity, ECS, IMDS
+    DefaultChain,
+    /// Named profile from `~/.aws/config` and `~/.aws/credentials`
+    Profile,
+    /// Role assumed with a web identity token, e.g. EKS service account
+    WebIdentity,
+    /// EC2 instance metadata service
+    Imds,
+}
+
+impl ObjStorage {
+    pub fn credentials_source(&self) -> CredentialsSource {
+        self.credentials.unwrap_or_else(|| {
+            if self.access_key_id.is_some() || self.access_key_id_file.is_some() {
+                CredentialsSource::Static
+            } else {
+                CredentialsSource::DefaultChain
+            }
+        })
+    }
+}
+
+/// Another S3 storage, e.g. in a different region or at a different provider, see [crate::media_store::ReplicatedMediaStore]
+#[derive(Debug, Deserialize, Clone)]
+pub struct ObjStorageReplica {
+    pub role: ReplicaRole,
+    #[serde(flatten)]
+    pub storage: ObjStorage,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReplicaRole {
+    /// Objects written to `obj_storage` are copied here in background, reads fall back here on errors
+    Secondary,
+    /// Reads fall back here on errors, nothing is written, e.g. a storage being moved away from
+    ReadFallback,
+}
+
+fn default_credentials_refresh_secs() -> u64 {
+    300
+}
+
+/// Where asset previews are stored
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct MediaStoreCfg {
+    pub backend: MediaStoreBackend,
+    /// Root directory of the `fs` backend
+    pub fs_root_dir: String,
+    pub key_layout: KeyLayoutCfg,
+    /// Layout the objects are being migrated from, previews not found in the current layout are looked up there.
+    /// Should be removed once `migrate-layout` command has moved all the objects.
+    pub previous_key_layout: Option<KeyLayoutCfg>,
+    /// Writes, reads and deletes a probe object at startup, so that missing permissions stop the application
+    /// right away instead of failing the asset processing
+    pub startup_self_check: bool,
+}
+
+/// How object keys are built, by default `media/{id}`, see [crate::media_store::KeyLayout]
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(default)]
+pub struct KeyLayoutCfg {
+    /// Number of 2-character prefixes of the ID, e.g. `media/ab/cd/abcd...` for 2.
+    /// Spreads the objects across S3 partitions.
+    pub shard_levels: u8,
+    /// Adds processing version, e.g. `media/v1/{id}`, so that old versions can be expired by lifecycle rules
+    pub versioned: bool,
+    /// Adds rendition name, e.g. `media/preview/{id}`
+    pub rendition_prefix: bool,
+}
+
+/// ID is a base58 encoded hash (about 44 characters), so a few levels are more than enough
+pub const MAX_SHARD_LEVELS: u8 = 4;
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum MediaStoreBackend {
+    /// S3 compatible object storage, configured in the `obj_storage` section
+    #[default]
+    S3,
+    /// Local directory, for development without S3
+    Fs,
+    /// Process memory, nothing survives a restart
+    Memory,
 }
 
+impl Default for MediaStoreCfg {
+    fn default() -> Self {
+        MediaStoreCfg {
+            backend: MediaStoreBackend::S3,
+            fs_root_dir: "./data/media".to_string(),
+            key_layout: KeyLayoutCfg::default(),
+            previous_key_layout: None,
+            startup_self_check: false,
+        }
+    }
+}
+
+/// Archive of the downloaded originals, so that assets survive their hosts going offline
 #[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct OriginalsCfg {
+    pub enabled: bool,
+    /// Separate bucket for the originals (`s3` media store only), the media bucket is used if not set
+    pub bucket: Option<String>,
+    /// Originals are stored under `{key_prefix}/{asset id}`
+    pub key_prefix: String,
+    /// Originals bigger than the limit of their class are not archived, 0 disables archiving of the class.
+    /// Downloads are limited by `asset_processor.file_max_size_bytes` regardless of these limits.
+    pub image_max_size_bytes: u64,
+    pub video_max_size_bytes: u64,
+    pub other_max_size_bytes: u64,
+}
+
+impl Default for OriginalsCfg {
+    fn default() -> Self {
+        OriginalsCfg {
+            enabled: false,
+            bucket: None,
+            key_prefix: "originals".to_string(),
+            image_max_size_bytes: 10 * MIB,
+            video_max_size_bytes: 100 * MIB,
+            other_max_size_bytes: 0,
+        }
+    }
+}
+
+/// URLs that are not downloaded, see [crate::url_filter::UrlFilter]
+#[derive(Debug, Deserialize, Clone, PartialEq, Eq, Default)]
+#[serde(default)]
+pub struct UrlFilterCfg {
+    /// Hosts, whose URLs are blocked, including their subdomains
+    pub deny_hosts: Vec<String>,
+    /// Regular expressions, URLs matching any of them are blocked
+    pub deny_patterns: Vec<String>,
+    /// If any of the allow lists is not empty, only URLs matching them are downloaded
+    pub allow_hosts: Vec<String>,
+    pub allow_patterns: Vec<String>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
 pub struct AssetProcessorCfg {
     pub resize_to: u32,
     pub file_max_size_bytes: u64,
+    /// Process URLs even if there is a preview produced by the current processing version already
+    #[serde(default)]
+    pub force_reprocess: bool,
+    /// Store previews of byte-identical images once, see [crate::media_store]
+    #[serde(default = "default_true")]
+    pub deduplicate: bool,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+pub struct MetricsCfg {
+    /// If disabled, no metrics are collected and the admin server has no `/metrics` endpoint
+    pub enabled: bool,
+    /// Address of a dedicated Prometheus exporter listener, e.g. "0.0.0.0:9100".
+    /// Metrics are available on the admin server regardless of this setting.
+    pub listen_address: Option<String>,
+    /// How often system metrics (CPU, memory) are collected
+    #[serde(default = "default_collection_interval_secs")]
+    pub collection_interval_secs: u64,
+    /// Labels added to every metric, e.g. `env` and `instance`
+    #[serde(default)]
+    pub global_labels: HashMap<String, String>,
+    pub push_gateway: Option<PushGatewayCfg>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct PushGatewayCfg {
+    pub endpoint: String,
+    pub interval_secs: u64,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl fmt::Debug for PushGatewayCfg {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PushGatewayCfg")
+            .field("endpoint", &self.endpoint)
+            .field("interval_secs", &self.interval_secs)
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|s|mask_creds(s)))
+            .finish()
+    }
+}
+
+fn default_collection_interval_secs() -> u64 {
+    60
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct LoggingCfg {
+    /// Log filter in the `RUST_LOG` format, e.g. "info,media_files_store=debug".
+    /// `RUST_LOG` environment variable, if set, takes precedence.
+    pub level: String,
+    pub format: LogFormat,
+}
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    /// Human readable output for local development
+    Text,
+    /// One JSON object per line, for log aggregation systems
+    Json,
+}
+
+impl Default for LoggingCfg {
+    fn default() -> Self {
+        LoggingCfg { level: "info".to_string(), format: LogFormat::Text }
+    }
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct TracingCfg {
+    /// Export spans to an OpenTelemetry collector
+    pub otlp_enabled: bool,
+    /// gRPC endpoint of the OpenTelemetry collector
+    pub otlp_endpoint: String,
+    pub service_name: String,
+    /// Share of traces (0.0 - 1.0) that are sampled, unless the decision is made by the caller
+    pub sample_ratio: f64,
+}
+
+impl Default for TracingCfg {
+    fn default() -> Self {
+        TracingCfg {
+            otlp_enabled: false,
+            otlp_endpoint: "http://127.0.0.1:4317".to_string(),
+            service_name: "media-files-store".to_string(),
+            sample_ratio: 1.0,
+        }
+    }
+}
+
+/// Applying config changes without restart.
+/// Reload can also be triggered by SIGHUP signal, regardless of these settings.
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct ConfigReloadCfg {
+    /// Poll the config directory for changed files
+    pub watch_files: bool,
+    pub poll_interval_secs: u64,
+}
+
+impl Default for ConfigReloadCfg {
+    fn default() -> Self {
+        ConfigReloadCfg { watch_files: true, poll_interval_secs: 5 }
+    }
+}
+
+/// How often a pipeline component reports it is alive while waiting for something,
+/// `health.heartbeat_timeout_secs` has to be longer than that
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde(default)]
+pub struct HealthCfg {
+    /// Pipeline component that hasn't reported for longer than this is considered hung
+    pub heartbeat_timeout_secs: u64,
+    /// Maximum time to wait for a dependency (S3, DAS node) to respond to a readiness check
+    pub check_timeout_secs: u64,
+}
+
+impl Default for HealthCfg {
+    fn default() -> Self {
+        HealthCfg { heartbeat_timeout_secs: 300, check_timeout_secs: 3 }
+    }
+}
+
+impl fmt::Debug for AdminServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AdminServer")
+            .field("enabled", &self.enabled)
+            .field("bind_address", &self.bind_address)
+            .field("port", &self.port)
+            .field("request_timeout_secs", &self.request_timeout_secs)
+            .field("max_request_body_bytes", &self.max_request_body_bytes)
+            .field("api_token", &self.api_token.as_deref().map(mask_creds))
+            .finish()
+    }
 }
 
 impl fmt::Debug for ObjStorage {
@@ -52,7 +441,17 @@ impl fmt::Debug for ObjStorage {
             .field("access_key_id", &self.access_key_id.as_ref().map(|s|mask_creds(s)))
             .field("secret_access_key", &self.secret_access_key.as_ref().map(|s|mask_creds(s)))
             .field("session_token", &self.session_token.as_ref().map(|s|mask_creds(s)))
+            .field("credentials", &self.credentials)
+            .field("access_key_id_file", &self.access_key_id_file)
+            .field("secret_access_key_file", &self.secret_access_key_file)
+            .field("session_token_file", &self.session_token_file)
+            .field("credentials_refresh_secs", &self.credentials_refresh_secs)
+            .field("profile_name", &self.profile_name)
+            .field("role_arn", &self.role_arn)
+            .field("web_identity_token_file", &self.web_identity_token_file)
             .field("bucket_for_media", &self.bucket_for_media)
+            .field("create_bucket_if_missing", &self.create_bucket_if_missing)
+            .field("multipart", &self.multipart)
             .finish()
     }
 }
@@ -61,54 +460,390 @@ impl fmt::Debug for ObjStorage {
 #[derive(Debug, Deserialize, Clone)]
 pub struct Settings {
     pub http_server: HttpServer,
+    pub admin_server: AdminServer,
+    #[serde(default)]
+    pub media_store: MediaStoreCfg,
     pub obj_storage: ObjStorage,
+    /// Storages in addition to `obj_storage` (which is the primary one), `s3` media store only
+    #[serde(default)]
+    pub obj_storage_replicas: Vec<ObjStorageReplica>,
     pub asset_processor: AssetProcessorCfg,
     pub das: DasCfg,
+    #[serde(default)]
+    pub url_filter: UrlFilterCfg,
+    #[serde(default)]
+    pub originals: OriginalsCfg,
+    #[serde(default)]
+    pub health: HealthCfg,
+    pub metrics: MetricsCfg,
+    #[serde(default)]
+    pub logging: LoggingCfg,
+    #[serde(default)]
+    pub tracing: TracingCfg,
+    #[serde(default)]
+    pub config_reload: ConfigReloadCfg,
     pub env: String,
 }
 
 impl Settings {
-    pub fn for_env(env_name: &str) -> Result<Self, ConfigError> {
-        Settings::load(Some(env_name), None)
+    /// Checks values that are syntactically correct, but make no sense for the application.
+    /// All the found problems are returned at once.
+    pub fn validate(&self) -> Result<(), Vec<InvalidField>> {
+        let mut v = Validator::default();
+
+        let http = &self.http_server;
+        v.positive("http_server.request_timeout_secs", http.request_timeout_secs);
+        v.positive("http_server.max_request_body_bytes", http.max_request_body_bytes as u64);
+        // S3 does not accept presigned URLs that live longer than 7 days
+        v.check("http_server.presigned_url_ttl_secs", (1..=7 * 24 * 3600).contains(&http.presigned_url_ttl_secs),
+            "must be between 1 and 604800 (7 days)");
+        if let Some(cdn_base_url) = &http.cdn_base_url {
+            v.url("http_server.cdn_base_url", cdn_base_url);
+        }
+
+        let admin = &self.admin_server;
+        v.check("admin_server.bind_address", !admin.bind_address.is_empty(), "must not be empty");
+        v.positive("admin_server.request_timeout_secs", admin.request_timeout_secs);
+        v.positive("admin_server.max_request_body_bytes", admin.max_request_body_bytes as u64);
+        if admin.enabled && http.enabled {
+            v.check("admin_server.port", admin.port != http.port, "must differ from http_server.port");
+        }
+        if let Some(api_token) = &admin.api_token {
+            v.check("admin_server.api_token", api_token.chars().count() >= MIN_API_TOKEN_LEN,
+                format!("must be at least {MIN_API_TOKEN_LEN} characters long"));
+        }
+
+        match self.media_store.backend {
+            MediaStoreBackend::S3 => {
+                self.validate_obj_storage(&mut v, "obj_storage", &self.obj_storage);
+                for (i, replica) in self.obj_storage_replicas.iter().enumerate() {
+                    self.validate_obj_storage(&mut v, &format!("obj_storage_replicas[{i}]"), &replica.storage);
+                }
+            },
+            MediaStoreBackend::Fs => v.check("media_store.fs_root_dir", !self.media_store.fs_root_dir.is_empty(), "must not be empty"),
+            MediaStoreBackend::Memory => (),
+        }
+        if self.media_store.backend != MediaStoreBackend::S3 {
+            v.check("obj_storage_replicas", self.obj_storage_replicas.is_empty(), "requires the s3 media store backend");
+        }
+
+        for (path, layout) in [
+            ("media_store.key_layout.shard_levels", Some(&self.media_store.key_layout)),
+            ("media_store.previous_key_layout.shard_levels", self.media_store.previous_key_layout.as_ref()),
+        ] {
+            if let Some(layout) = layout {
+                v.check(path, layout.shard_levels <= MAX_SHARD_LEVELS, format!("must not be greater than {MAX_SHARD_LEVELS}"));
+            }
+        }
+        v.check("media_store.previous_key_layout",
+            self.media_store.previous_key_layout != Some(self.media_store.key_layout),
+            "must differ from key_layout");
+
+        if self.originals.enabled {
+            let prefix = &self.originals.key_prefix;
+            v.check("originals.key_prefix",
+                !prefix.is_empty() && !prefix.starts_with('/') && !prefix.ends_with('/'),
+                "must be non-empty and must not start or end with '/'");
+            // Roots of the preview keys, see [crate::media_store::KeyLayout], and of the denylist
+            v.check("originals.key_prefix", !["media", "content", "denylist"].contains(&prefix.as_str()),
+                "is used for the previews or the denylist");
+            if let Some(bucket) = &self.originals.bucket {
+                v.check("originals.bucket", !bucket.is_empty(), "must not be empty");
+                v.check("originals.bucket", self.media_store.backend == MediaStoreBackend::S3, "requires the s3 media store backend");
+            }
+        }
+
+        v.positive("asset_processor.resize_to", self.asset_processor.resize_to as u64);
+        v.positive("asset_processor.file_max_size_bytes", self.asset_processor.file_max_size_bytes);
+
+        let url_filter = &self.url_filter;
+        for (path, hosts) in [("url_filter.deny_hosts", &url_filter.deny_hosts), ("url_filter.allow_hosts", &url_filter.allow_hosts)] {
+            for host in hosts {
+                v.check(path, !host.is_empty() && !host.contains(['/', ':', '*']),
+                    format!("\"{host}\" is not a host name, e.g. \"example.com\""));
+            }
+        }
+        for (path, patterns) in [("url_filter.deny_patterns", &url_filter.deny_patterns), ("url_filter.allow_patterns", &url_filter.allow_patterns)] {
+            for pattern in patterns {
+                if let Err(e) = regex::Regex::new(pattern) {
+                    v.check(path, false, format!("invalid regular expression: {e}"));
+                }
+            }
+        }
+
+        if self.das.enabled {
+            v.url("das.grpc_address", &self.das.grpc_address);
+            v.positive("das.fetch_batch_size", self.das.fetch_batch_size as u64);
+            v.positive("das.number_of_workers", self.das.number_of_workers as u64);
+        }
+
+        v.check("health.heartbeat_timeout_secs", self.health.heartbeat_timeout_secs > HEARTBEAT_INTERVAL.as_secs(),
+            format!("must be greater than the heartbeat interval ({}s)", HEARTBEAT_INTERVAL.as_secs()));
+        v.positive("health.check_timeout_secs", self.health.check_timeout_secs);
+
+        let metrics = &self.metrics;
+        if metrics.enabled {
+            v.positive("metrics.collection_interval_secs", metrics.collection_interval_secs);
+            if let Some(listen_address) = &metrics.listen_address {
+                v.check("metrics.listen_address", listen_address.parse::<SocketAddr>().is_ok(),
+                    "must be a socket address, e.g. \"0.0.0.0:9100\"");
+            }
+            if let Some(push_gateway) = &metrics.push_gateway {
+                v.check("metrics.push_gateway", metrics.listen_address.is_none(),
+                    "cannot be used together with metrics.listen_address");
+                v.url("metrics.push_gateway.endpoint", &push_gateway.endpoint);
+                v.positive("metrics.push_gateway.interval_secs", push_gateway.interval_secs);
+            }
+        }
+
+        if let Err(e) = tracing_subscriber::EnvFilter::try_new(&self.logging.level) {
+            v.check("logging.level", false, format!("invalid log filter: {e}"));
+        }
+
+        let tracing = &self.tracing;
+        v.check("tracing.sample_ratio", (0.0..=1.0).contains(&tracing.sample_ratio), "must be between 0.0 and 1.0");
+        v.check("tracing.service_name", !tracing.service_name.is_empty(), "must not be empty");
+        if tracing.otlp_enabled {
+            v.url("tracing.otlp_endpoint", &tracing.otlp_endpoint);
+        }
+
+        if self.config_reload.watch_files {
+            v.positive("config_reload.poll_interval_secs", self.config_reload.poll_interval_secs);
+        }
+
+        v.finish()
     }
 
-    /// This method should be used for production.
-    /// It loads application configuration based on the environment variables.
-    pub fn default() -> Result<Self, ConfigError> {
-        Settings::load(None, None)
+    /// `section` is the path of the storage config, e.g. "obj_storage"
+    fn validate_obj_storage(&self, v: &mut Validator, section: &str, storage: &ObjStorage) {
+        v.check(format!("{section}.bucket_for_media"), !storage.bucket_for_media.is_empty(), "must not be empty");
+        if let Some(endpoint) = &storage.endpoint {
+            v.url(format!("{section}.endpoint"), endpoint);
+        }
+        for (path, value, file) in [
+            (format!("{section}.access_key_id_file"), &storage.access_key_id, &storage.access_key_id_file),
+            (format!("{section}.secret_access_key_file"), &storage.secret_access_key, &storage.secret_access_key_file),
+            (format!("{section}.session_token_file"), &storage.session_token, &storage.session_token_file),
+        ] {
+            if let Some(file) = file {
+                v.check(&path, value.is_none(), "cannot be set together with the value itself");
+                v.file(path, file);
+            }
+        }
+        match storage.credentials_source() {
+            CredentialsSource::Static => {
+                v.check(format!("{section}.access_key_id"), storage.access_key_id.is_some() || storage.access_key_id_file.is_some(),
+                    "is required for static credentials (or access_key_id_file)");
+                v.check(format!("{section}.secret_access_key"), storage.secret_access_key.is_some() || storage.secret_access_key_file.is_some(),
+                    "is required for static credentials (or secret_access_key_file)");
+                v.positive(format!("{section}.credentials_refresh_secs"), storage.credentials_refresh_secs);
+            },
+            CredentialsSource::WebIdentity => {
+                v.check(format!("{section}.role_arn"), storage.role_arn.is_some() == storage.web_identity_token_file.is_some(),
+                    "role_arn and web_identity_token_file must be set together");
+                if let Some(file) = &storage.web_identity_token_file {
+                    v.file(format!("{section}.web_identity_token_file"), file);
+                }
+            },
+            CredentialsSource::DefaultChain | CredentialsSource::Profile | CredentialsSource::Imds => (),
+        }
+
+        let multipart = &storage.multipart;
+        v.positive(format!("{section}.multipart.threshold_bytes"), multipart.threshold_bytes);
+        v.check(format!("{section}.multipart.part_size_bytes"),
+            (MULTIPART_MIN_PART_SIZE ..= MULTIPART_MAX_PART_SIZE).contains(&multipart.part_size_bytes),
+            format!("must be between {MULTIPART_MIN_PART_SIZE} and {MULTIPART_MAX_PART_SIZE}"));
+        v.check(format!("{section}.multipart.part_size_bytes"),
+            multipart.part_size_bytes.saturating_mul(MULTIPART_MAX_PARTS) >= self.asset_processor.file_max_size_bytes,
+            format!("is too small to upload asset_processor.file_max_size_bytes in {MULTIPART_MAX_PARTS} parts"));
+        v.positive(format!("{section}.multipart.concurrency"), multipart.concurrency as u64);
     }
+}
 
-    fn load(env_name: Option<&str>, config_path: Option<&str>) -> Result<Self, ConfigError> {
+/// Location of the config files: directory and the profile
+#[derive(Debug, Clone)]
+pub struct ConfigSource {
+    pub env: String,
+    pub config_dir: String,
+}
 
-        let configs_path = config_path.map(|s|s.to_string()).unwrap_or(
+impl ConfigSource {
+    /// If not specified, `env` and `config_dir` are taken from `RUN_ENV` and `RUN_CONFIG_DIR` environment variables,
+    /// and default to `local` and `./config` respectively.
+    pub fn new(env_name: Option<&str>, config_path: Option<&str>) -> Self {
+        let config_dir = config_path.map(|s|s.to_string()).unwrap_or(
             std::env::var("RUN_CONFIG_DIR").unwrap_or_else(|_| DEFAULT_CONFIG_FILE_PREFIX.to_string())
         );
 
         let env = env_name.map(|s|s.to_string()).unwrap_or(
             std::env::var("RUN_ENV").unwrap_or_else(|_| "local".into())
         );
-        println!("Using profile: {}", &env);
 
+        ConfigSource { env, config_dir }
+    }
+
+    pub fn load(&self) -> Result<Settings, SettingsError> {
         let raw_config = Config::builder()
             // Start off by merging in the "default" configuration file
-            .add_source(File::with_name(&default_config_file_path(&configs_path)))
+            .add_source(File::with_name(&default_config_file_path(&self.config_dir)))
             // Add in the current environment file, Default to 'development' env
             // Note that this file is _optional_
             .add_source(
-                File::with_name(&format!("{}/{}", configs_path, env)).required(false),
+                File::with_name(&format!("{}/{}", self.config_dir, self.env)).required(false),
             )
             // Add in settings from the environment (with a prefix of APP)
             // Eg.. `APP_SERVER__PORT=8081 ./target/app` would set the `port` key
             .add_source(Environment::with_prefix("app").separator("__"))
-            .set_override("env", env)?
+            .set_override("env", self.env.as_str())?
             .build()?;
 
-        raw_config.try_deserialize()
+        let settings: Settings = raw_config.try_deserialize()?;
+        settings.validate().map_err(SettingsError::Invalid)?;
+        Ok(settings)
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum SettingsError {
+    #[error("Cannot load config: {0}")]
+    Load(#[from] ConfigError),
+    #[error("Invalid config:\n{}", .0.iter().map(|e| format!("  {e}")).collect::<Vec<_>>().join("\n"))]
+    Invalid(Vec<InvalidField>),
+}
+
+/// A config value that has failed the validation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidField {
+    /// Path to the field, e.g. "das.grpc_address"
+    pub path: String,
+    pub reason: String,
+}
+
+impl fmt::Display for InvalidField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.reason)
     }
 }
 
+#[derive(Default)]
+struct Validator {
+    errors: Vec<InvalidField>,
+}
+
+impl Validator {
+    fn check(&mut self, path: impl Into<String>, ok: bool, reason: impl Into<String>) {
+        if !ok {
+            self.errors.push(InvalidField { path: path.into(), reason: reason.into() });
+        }
+    }
+
+    fn positive(&mut self, path: impl Into<String>, value: u64) {
+        self.check(path, value > 0, "must be greater than 0");
+    }
+
+    /// Absolute URL with a scheme and a host, e.g. "http://127.0.0.1:9091"
+    fn url(&mut self, path: impl Into<String>, value: &str) {
+        let is_valid = value.parse::<http::Uri>()
+            .is_ok_and(|uri| uri.scheme().is_some() && uri.host().is_some_and(|h| !h.is_empty()));
+        self.check(path, is_valid, format!("\"{value}\" is not a valid URL"));
+    }
+
+    fn file(&mut self, path: impl Into<String>, value: &str) {
+        self.check(path, std::path::Path::new(value).is_file(), format!("file \"{value}\" does not exist"));
+    }
+
+    fn finish(self) -> Result<(), Vec<InvalidField>> {
+        if self.errors.is_empty() {
+            Ok(())
+        } else {
+            Err(self.errors)
+        }
+    }
+}
+
+/// Hides secrets in logs: only the first characters of long enough secrets are shown
 fn mask_creds(s: &str) -> String {
-    let mut result = s.to_owned();
-    result.replace_range( 2 .. s.len(), "*".repeat(s.len()-2).as_str());
-    result
-}
\ No newline at end of file
+    const VISIBLE_CHARS: usize = 2;
+    const MIN_LEN_TO_SHOW_PREFIX: usize = 8;
+
+    let len = s.chars().count();
+    if len < MIN_LEN_TO_SHOW_PREFIX {
+        return "*".repeat(len);
+    }
+    s.chars().take(VISIBLE_CHARS).chain(std::iter::repeat_n('*', len - VISIBLE_CHARS)).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn repo_settings() -> Settings {
+        ConfigSource::new(Some("local"), Some(DEFAULT_CONFIG_FILE_PREFIX)).load().unwrap()
+    }
+
+    #[test]
+    fn test_validation_reports_all_invalid_fields() {
+        let mut settings = repo_settings();
+        settings.asset_processor.resize_to = 0;
+        settings.das.number_of_workers = 0;
+        settings.das.fetch_batch_size = 0;
+        settings.das.grpc_address = "127.0.0.1:9091 ".to_string();
+        settings.tracing.sample_ratio = 1.5;
+        settings.url_filter.deny_hosts = vec!["example.com".to_string(), "https://junk.com/".to_string()];
+        settings.url_filter.allow_patterns = vec!["^https://(".to_string()];
+
+        let errors = settings.validate().unwrap_err();
+        let paths: Vec<_> = errors.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, vec![
+            "asset_processor.resize_to",
+            "url_filter.deny_hosts",
+            "url_filter.allow_patterns",
+            "das.grpc_address",
+            "das.fetch_batch_size",
+            "das.number_of_workers",
+            "tracing.sample_ratio",
+        ]);
+
+        let message = SettingsError::Invalid(errors).to_string();
+        assert!(message.contains("  das.number_of_workers: must be greater than 0"), "{message}");
+    }
+
+    #[test]
+    fn test_replicas_are_validated_separately() {
+        let raw_config = Config::builder()
+            .add_source(File::with_name(&default_config_file_path(DEFAULT_CONFIG_FILE_PREFIX)))
+            .add_source(File::from_str(r#"
+                [[obj_storage_replicas]]
+                role = "secondary"
+                region = "eu-west-1"
+                bucket_for_media = "media-replica"
+                access_key_id = "admin"
+                [obj_storage_replicas.multipart]
+                part_size_bytes = 1024
+            "#, config::FileFormat::Toml))
+            .set_override("env", "test").unwrap()
+            .build().unwrap();
+        let settings: Settings = raw_config.try_deserialize().unwrap();
+        let replica = &settings.obj_storage_replicas[0];
+        assert_eq!(replica.role, ReplicaRole::Secondary);
+        assert_eq!(replica.storage.bucket_for_media, "media-replica");
+        assert_eq!(replica.storage.multipart.threshold_bytes, 16 * MIB);
+
+        let errors = settings.validate().unwrap_err();
+        let paths: Vec<_> = errors.iter().map(|e| e.path.as_str()).collect();
+        assert_eq!(paths, [
+            "obj_storage_replicas[0].secret_access_key",
+            "obj_storage_replicas[0].multipart.part_size_bytes",
+            "obj_storage_replicas[0].multipart.part_size_bytes",
+        ]);
+    }
+
+    #[test]
+    fn test_mask_creds() {
+        assert_eq!(mask_creds(""), "");
+        assert_eq!(mask_creds("a"), "*");
+        assert_eq!(mask_creds("admin"), "*****");
+        assert_eq!(mask_creds("password"), "pa******");
+        assert_eq!(mask_creds("ключ-секрет"), "кл*********");
+    }
+}
@@ -1,6 +1,6 @@
 use async_trait::async_trait;
 
-use crate::{grpc::asseturls::{asset_url_service_client::AssetUrlServiceClient, url_download_details::DlResult, DownloadError, DownloadResultsRequest, DownloadSuccess, GetAssetUrlsRequest, UrlDownloadDetails}, download::DlError};
+use crate::{grpc::asseturls::{asset_url_service_client::AssetUrlServiceClient, url_download_details::DlResult, DownloadError, DownloadResultsRequest, DownloadSuccess, GetAssetUrlsRequest, UrlDownloadDetails}, download::DlError, telemetry};
 
 /// Interface for DAS node (utility-chain) client
 #[async_trait]
@@ -15,6 +15,9 @@ pub trait DasClient {
     /// ## Arguments:
     /// * `asset_result` - collection of asset download and processing results
     async fn notify_finished(&self, asset_result: Vec<UrlDlResult>);
+
+    /// Checks that the DAS node is reachable
+    async fn check_connection(&self) -> anyhow::Result<()>;
 }
 
 pub struct UtilityChainClient {
@@ -23,22 +26,33 @@ pub struct UtilityChainClient {
 
 #[async_trait]
 impl DasClient for UtilityChainClient {
+    #[tracing::instrument(name = "das_fetch_assets", skip(self))]
     async fn fetch_assets_for_downloading(&self, amount: u32) -> Vec<String> {
         let url = self.das_url.clone();
-        let Ok(mut client) = AssetUrlServiceClient::connect(url).await else {
-            return Vec::new();
+        let mut client = match AssetUrlServiceClient::connect(url).await {
+            Ok(client) => client,
+            Err(err) => {
+                tracing::warn!(error = %err, das_url = %self.das_url, "Cannot connect to DAS node");
+                return Vec::new();
+            },
         };
-        let request = tonic::Request::new(GetAssetUrlsRequest { count: amount});
+        let mut request = tonic::Request::new(GetAssetUrlsRequest { count: amount});
+        telemetry::inject_trace_context(&mut request);
     
         match client.get_asset_urls_to_download(request).await {
-            Ok(resp) => resp.into_inner().urls,
+            Ok(resp) => {
+                let urls = resp.into_inner().urls;
+                tracing::debug!(urls = urls.len(), "Fetched URLs to download");
+                urls
+            },
             Err(err) => {
-                eprintln!("{err:?}");
+                tracing::warn!(error = %err, "Failed to fetch URLs to download");
                 Vec::new()
             },
         }
     }
 
+    #[tracing::instrument(name = "das_notify_finished", skip_all, fields(results = asset_result.len()))]
     async fn notify_finished(&self, asset_result: Vec<UrlDlResult>) {
         let results: Vec<UrlDownloadDetails> = asset_result.into_iter()
             .map(|UrlDlResult {url, outcome }|
@@ -46,16 +60,27 @@ impl DasClient for UtilityChainClient {
             )
             .collect::<Vec<_>>();
 
+        let results_number = results.len();
         let url = self.das_url.clone();
-        let Ok(mut client) = AssetUrlServiceClient::connect(url).await else {
-            return ();
+        let mut client = match AssetUrlServiceClient::connect(url).await {
+            Ok(client) => client,
+            Err(err) => {
+                tracing::warn!(error = %err, das_url = %self.das_url, results = results_number, "Cannot connect to DAS node, results are lost");
+                return;
+            },
         };
-        let request = tonic::Request::new(DownloadResultsRequest { results });
+        let mut request = tonic::Request::new(DownloadResultsRequest { results });
+        telemetry::inject_trace_context(&mut request);
         match client.submit_download_result(request).await {
-            Ok(_) => (),
-            Err(_) => (),
+            Ok(_) => tracing::debug!(results = results_number, "Submitted download results"),
+            Err(err) => tracing::warn!(error = %err, results = results_number, "Failed to submit download results"),
         }
     }
+
+    async fn check_connection(&self) -> anyhow::Result<()> {
+        AssetUrlServiceClient::connect(self.das_url.clone()).await?;
+        Ok(())
+    }
 }
 
 /// URL processing result
@@ -81,6 +106,12 @@ impl DlOutcome {
     pub fn corrupted_asset(details: String) -> DlOutcome {
         DlOutcome::Fail { err: DlError::CorruptedAsset(details) }
     }
+    pub fn taken_down() -> DlOutcome {
+        DlOutcome::Fail { err: DlError::TakenDown }
+    }
+    pub fn blocked(rule: String) -> DlOutcome {
+        DlOutcome::Fail { err: DlError::Blocked(rule) }
+    }
 }
 
 impl From<DlOutcome> for DlResult {
@@ -104,6 +135,10 @@ impl From<DlError> for DownloadError {
             E::UnsupportedFormat(_) => DownloadError::NotSupportedFormat,
             E::CorruptedAsset(_) => DownloadError::CorruptedAsset,
             E::TooManyRequests => DownloadError::TooManyRequests,
+            E::TakenDown => DownloadError::TakenDown,
+            E::Blocked(_) => DownloadError::Blocked,
+            // Failure on our side, so DAS node retries the URL as if the asset host has failed
+            E::StorageError(_) => DownloadError::ServerError,
         }
     }
 }
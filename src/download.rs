@@ -1,8 +1,18 @@
+use std::sync::Arc;
+
 use bytes::Bytes;
 use http::StatusCode;
+use reqwest::redirect;
 use thiserror::Error;
 
-use crate::{media_type::Mime, app_metrics::{CAT_STATUS, MET_DOWNLOADS}};
+use tokio::time::Instant;
+
+use crate::{
+    media_type::Mime,
+    media_store::StorageError,
+    url_filter::UrlFilter,
+    app_metrics::{CAT_OUTCOME, CAT_STATUS, MET_BYTES_DOWNLOADED, MET_DOWNLOADS, MET_DOWNLOAD_DURATION},
+};
 
 /// Represents download and processing error
 #[derive(Error, Debug)]
@@ -23,18 +33,88 @@ pub enum DlError {
     UnsupportedFormat(String),
     #[error("Processing error: {0}")]
     CorruptedAsset(String),
+    /// URL or content is in the denylist, see [crate::takedown]
+    #[error("Taken down")]
+    TakenDown,
+    /// URL is not downloaded according to the filter rule, see [crate::url_filter]
+    #[error("Blocked by rule {0}")]
+    Blocked(String),
+    /// Preview cannot be stored or the denylist cannot be checked, the URL has to be processed again later
+    #[error("Storage error: {0}")]
+    StorageError(#[from] StorageError),
 }
 
+/// Same limit as the default redirect policy of reqwest
+const MAX_REDIRECTS: usize = 10;
+
+/// Redirect to a URL blocked by the URL filter, with the rule that has blocked it
+#[derive(Error, Debug)]
+#[error("Redirect is blocked by rule {0}")]
+struct RedirectBlocked(String);
+
 impl From<reqwest::Error> for DlError {
     fn from(_: reqwest::Error) -> Self {
         DlError::DownloadFailed
     }
 }
 
-pub async fn download(url: &str, file_max_size: u64) -> std::result::Result<(Bytes, Mime), DlError> {
-    let Ok(resp) = reqwest::get(url).await else {
-        metrics::counter!(MET_DOWNLOADS, CAT_STATUS => "not_found").increment(1);
-        return Err(DlError::NotFound);
+impl DlError {
+    /// Label value used in metrics
+    pub fn metric_label(&self) -> &'static str {
+        match self {
+            DlError::FileTooLarge(_) => "too_large",
+            DlError::DownloadFailed => "download_failed",
+            DlError::NotFound => "not_found",
+            DlError::TooManyRequests => "too_many_requests",
+            DlError::ServerError => "server_error",
+            DlError::UnsupportedFormat(_) => "unsupported_format",
+            DlError::CorruptedAsset(_) => "corrupted_asset",
+            DlError::TakenDown => "taken_down",
+            DlError::Blocked(_) => "blocked",
+            DlError::StorageError(_) => "storage_error",
+        }
+    }
+}
+
+/// Downloads the asset. Redirects are checked by the URL filter as well,
+/// so that an allowed host cannot redirect to a blocked one.
+pub async fn download(url: &str, file_max_size: u64, url_filter: Arc<UrlFilter>) -> std::result::Result<(Bytes, Mime), DlError> {
+    let start = Instant::now();
+    let result = fetch(url, file_max_size, url_filter).await;
+
+    let outcome = match &result {
+        Ok((bytes, _)) => {
+            metrics::counter!(MET_BYTES_DOWNLOADED).increment(bytes.len() as u64);
+            "success"
+        },
+        Err(err) => err.metric_label(),
+    };
+    metrics::histogram!(MET_DOWNLOAD_DURATION, CAT_OUTCOME => outcome).record(start.elapsed().as_secs_f64());
+
+    result
+}
+
+async fn fetch(url: &str, file_max_size: u64, url_filter: Arc<UrlFilter>) -> std::result::Result<(Bytes, Mime), DlError> {
+    let redirect_policy = redirect::Policy::custom(move |attempt| {
+        if attempt.previous().len() > MAX_REDIRECTS {
+            return attempt.error("too many redirects");
+        }
+        match url_filter.blocking_rule(attempt.url().as_str()) {
+            Some(rule) => attempt.error(RedirectBlocked(rule)),
+            None => attempt.follow(),
+        }
+    });
+    let client = reqwest::Client::builder().redirect(redirect_policy).build()?;
+    let resp = match client.get(url).send().await {
+        Ok(resp) => resp,
+        Err(err) => {
+            if let Some(RedirectBlocked(rule)) = find_source(&err) {
+                metrics::counter!(MET_DOWNLOADS, CAT_STATUS => "blocked").increment(1);
+                return Err(DlError::Blocked(rule.clone()));
+            }
+            metrics::counter!(MET_DOWNLOADS, CAT_STATUS => "not_found").increment(1);
+            return Err(DlError::NotFound);
+        },
     };
     if resp.status().is_client_error() {
         metrics::counter!(MET_DOWNLOADS, CAT_STATUS => "not_found").increment(1);
@@ -63,7 +143,7 @@ pub async fn download(url: &str, file_max_size: u64) -> std::result::Result<(Byt
     if let Some(size) = resp.content_length() {
         if size > file_max_size {
             metrics::counter!(MET_DOWNLOADS, CAT_STATUS => "too_large").increment(1);
-            return Err(DlError::FileTooLarge(size).into());
+            return Err(DlError::FileTooLarge(size));
         }
     }
     metrics::counter!(MET_DOWNLOADS, CAT_STATUS => "success").increment(1);
@@ -72,3 +152,64 @@ pub async fn download(url: &str, file_max_size: u64) -> std::result::Result<(Byt
     
     Ok((bytes, content_type))
 }
+
+fn find_source<'a, T: std::error::Error + 'static>(err: &'a (dyn std::error::Error + 'static)) -> Option<&'a T> {
+    let mut source = err.source();
+    while let Some(err) = source {
+        if let Some(found) = err.downcast_ref::<T>() {
+            return Some(found);
+        }
+        source = err.source();
+    }
+    None
+}
+
+/// Serves the routes on a random local port, as an asset host would, and returns its base URL
+#[cfg(test)]
+pub(crate) async fn serve_for_test(routes: axum::Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, routes).await });
+    format!("http://{address}")
+}
+
+/// Image served by [serve_test_image]
+#[cfg(test)]
+pub(crate) const TEST_IMAGE: &str = "test_data/img/small.png";
+
+/// Asset host, that serves [TEST_IMAGE] at any path
+#[cfg(test)]
+pub(crate) async fn serve_test_image() -> String {
+    let image = Bytes::from(std::fs::read(TEST_IMAGE).unwrap());
+    let routes = axum::Router::new().route("/*path", axum::routing::get(move || {
+        let image = image.clone();
+        async move { ([(http::header::CONTENT_TYPE, "image/png")], image) }
+    }));
+    serve_for_test(routes).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::{response::Redirect, routing::get, Router};
+    use crate::configs::UrlFilterCfg;
+
+    #[tokio::test]
+    async fn test_redirects_are_filtered() {
+        let image_url = format!("{}/1.png", serve_test_image().await);
+        // Blocked before connecting, so that the host doesn't have to exist
+        let blocked_url = image_url.replace("127.0.0.1", "localhost");
+        let redirects = Router::new()
+            .route("/allowed", get(move || async move { Redirect::temporary(&image_url) }))
+            .route("/blocked", get(move || async move { Redirect::temporary(&blocked_url) }));
+        let base_url = serve_for_test(redirects).await;
+        let url_filter = Arc::new(UrlFilter::new(&UrlFilterCfg { deny_hosts: vec!["localhost".to_string()], ..Default::default() }).unwrap());
+
+        let (bytes, mime) = download(&format!("{base_url}/allowed"), 1024 * 1024, url_filter.clone()).await.unwrap();
+        assert_eq!(bytes.as_ref(), std::fs::read(TEST_IMAGE).unwrap());
+        assert_eq!(mime.str(), "image/png");
+
+        let result = download(&format!("{base_url}/blocked"), 1024 * 1024, url_filter).await;
+        assert!(matches!(result, Err(DlError::Blocked(rule)) if rule == "deny_host:localhost"));
+    }
+}
@@ -0,0 +1,229 @@
+//! Liveness and readiness checks of the service.
+//!
+//! Liveness covers only the state of the process itself, i.e. whether the downloading
+//! pipeline components are still running, while readiness also checks
+//! that the external dependencies (object storage and DAS node) are reachable.
+use std::{
+    future::Future,
+    sync::{atomic::{AtomicU64, Ordering}, Arc, RwLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
+use serde::Serialize;
+
+use crate::{configs::{HealthCfg, HEARTBEAT_INTERVAL}, das_client::DasClient, media_store::MediaStore};
+
+/// Timestamp of the last moment a pipeline component has reported it is alive
+pub struct Heartbeat(AtomicU64);
+
+impl Heartbeat {
+    pub fn new() -> Heartbeat {
+        let heartbeat = Heartbeat(AtomicU64::new(0));
+        heartbeat.beat();
+        heartbeat
+    }
+
+    pub fn beat(&self) {
+        self.0.store(now_millis(), Ordering::Relaxed);
+    }
+
+    /// Time passed since the last beat
+    pub fn age(&self) -> Duration {
+        Duration::from_millis(now_millis().saturating_sub(self.0.load(Ordering::Relaxed)))
+    }
+
+    /// Awaits the given future, periodically beating,
+    /// so that waiting for a new task or a free queue slot is not mistaken for a hang.
+    pub async fn beat_while<F: Future>(&self, fut: F) -> F::Output {
+        tokio::pin!(fut);
+        loop {
+            self.beat();
+            tokio::select! {
+                res = &mut fut => return res,
+                _ = tokio::time::sleep(HEARTBEAT_INTERVAL) => (),
+            }
+        }
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64
+}
+
+/// Heartbeats of the downloading pipeline components
+pub struct PipelineHeartbeats {
+    pub poller: Heartbeat,
+    workers: RwLock<Vec<Arc<Heartbeat>>>,
+}
+
+impl PipelineHeartbeats {
+    pub fn new() -> PipelineHeartbeats {
+        PipelineHeartbeats { poller: Heartbeat::new(), workers: RwLock::new(Vec::new()) }
+    }
+
+    pub fn register_worker(&self) -> Arc<Heartbeat> {
+        let heartbeat = Arc::new(Heartbeat::new());
+        self.workers.write().unwrap().push(heartbeat.clone());
+        heartbeat
+    }
+
+    pub fn unregister_worker(&self, heartbeat: &Arc<Heartbeat>) {
+        self.workers.write().unwrap().retain(|h| !Arc::ptr_eq(h, heartbeat));
+    }
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CheckStatus {
+    Up,
+    Down,
+}
+
+#[derive(Serialize)]
+pub struct CheckResult {
+    pub status: CheckStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<String>,
+}
+
+impl CheckResult {
+    fn up(details: Option<String>) -> CheckResult {
+        CheckResult { status: CheckStatus::Up, details }
+    }
+    fn down(details: String) -> CheckResult {
+        CheckResult { status: CheckStatus::Down, details: Some(details) }
+    }
+}
+
+#[derive(Serialize)]
+pub struct HealthReport {
+    pub status: CheckStatus,
+    pub checks: std::collections::BTreeMap<&'static str, CheckResult>,
+}
+
+impl HealthReport {
+    fn new(checks: Vec<(&'static str, CheckResult)>) -> HealthReport {
+        let status = if checks.iter().all(|(_, c)| c.status == CheckStatus::Up) {
+            CheckStatus::Up
+        } else {
+            CheckStatus::Down
+        };
+        HealthReport { status, checks: checks.into_iter().collect() }
+    }
+}
+
+impl IntoResponse for HealthReport {
+    fn into_response(self) -> axum::response::Response {
+        let status = match self.status {
+            CheckStatus::Up => StatusCode::OK,
+            CheckStatus::Down => StatusCode::SERVICE_UNAVAILABLE,
+        };
+        (status, Json(self)).into_response()
+    }
+}
+
+/// Performs the service health checks. Components that are disabled
+/// in the configuration are not checked.
+pub struct HealthChecker {
+    pub media_storage: Arc<dyn MediaStore + Send + Sync>,
+    pub das_client: Option<Arc<dyn DasClient + Send + Sync + 'static>>,
+    pub pipeline: Option<Arc<PipelineHeartbeats>>,
+    pub cfg: HealthCfg,
+}
+
+impl HealthChecker {
+    pub async fn liveness(&self) -> HealthReport {
+        let mut checks = Vec::new();
+        if let Some(pipeline) = &self.pipeline {
+            checks.push(("pipeline", self.check_pipeline(pipeline)));
+        }
+        HealthReport::new(checks)
+    }
+
+    pub async fn readiness(&self) -> HealthReport {
+        let mut checks = Vec::new();
+
+        let (storage, das) = tokio::join!(
+            self.with_timeout(self.check_storage()),
+            async {
+                match &self.das_client {
+                    Some(das_client) => Some(self.with_timeout(check_das(das_client.as_ref())).await),
+                    None => None,
+                }
+            },
+        );
+        checks.push(("storage", storage));
+        if let Some(das) = das {
+            checks.push(("das", das));
+        }
+        if let Some(pipeline) = &self.pipeline {
+            checks.push(("pipeline", self.check_pipeline(pipeline)));
+        }
+
+        HealthReport::new(checks)
+    }
+
+    async fn check_storage(&self) -> CheckResult {
+        match self.media_storage.check().await {
+            Ok(()) => CheckResult::up(None),
+            Err(err) => CheckResult::down(err.to_string()),
+        }
+    }
+
+    fn check_pipeline(&self, pipeline: &PipelineHeartbeats) -> CheckResult {
+        let timeout = Duration::from_secs(self.cfg.heartbeat_timeout_secs);
+
+        let poller_age = pipeline.poller.age();
+        let workers = pipeline.workers.read().unwrap();
+        let hung_workers = workers.iter().filter(|w| w.age() > timeout).count();
+        let details = format!(
+            "poller last seen {}s ago, {} of {} workers alive",
+            poller_age.as_secs(), workers.len() - hung_workers, workers.len()
+        );
+
+        if poller_age > timeout || workers.is_empty() || hung_workers > 0 {
+            CheckResult::down(details)
+        } else {
+            CheckResult::up(Some(details))
+        }
+    }
+
+    async fn with_timeout(&self, check: impl Future<Output = CheckResult>) -> CheckResult {
+        let timeout = Duration::from_secs(self.cfg.check_timeout_secs);
+        tokio::time::timeout(timeout, check).await
+            .unwrap_or_else(|_| CheckResult::down(format!("no response within {}s", timeout.as_secs())))
+    }
+}
+
+async fn check_das(das_client: &(dyn DasClient + Send + Sync)) -> CheckResult {
+    match das_client.check_connection().await {
+        Ok(()) => CheckResult::up(None),
+        Err(err) => CheckResult::down(err.to_string()),
+    }
+}
+
+pub async fn liveness_handler(State(checker): State<Arc<HealthChecker>>) -> HealthReport {
+    checker.liveness().await
+}
+
+pub async fn readiness_handler(State(checker): State<Arc<HealthChecker>>) -> HealthReport {
+    checker.readiness().await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_report_is_down_if_any_check_is_down() {
+        let report = HealthReport::new(vec![
+            ("storage", CheckResult::up(None)),
+            ("das", CheckResult::down("unreachable".to_string())),
+        ]);
+        assert!(report.status == CheckStatus::Down);
+
+        let report = HealthReport::new(vec![("storage", CheckResult::up(None))]);
+        assert!(report.status == CheckStatus::Up);
+    }
+}
@@ -1,39 +1,68 @@
-use std::{collections::HashMap, future::ready, sync::Arc};
+use std::{collections::HashMap, sync::Arc, time::Duration};
 
 use axum::{
-    body::Body, extract::{Path, Query, State}, http::StatusCode, response::{IntoResponse, Response}, routing::get, Router
+    body::Body, extract::{Path, Query, State}, http::StatusCode, response::{IntoResponse, Response}, routing::get, Json, Router
 };
-use http::header::CONTENT_TYPE;
-use tokio::time::Instant;
+use bytes::Bytes;
+use http::header::{CONTENT_DISPOSITION, CONTENT_SECURITY_POLICY, CONTENT_TYPE, LOCATION, X_CONTENT_TYPE_OPTIONS};
+use serde::Serialize;
 use tokio_util::io::ReaderStream;
 
-use crate::{configs::HttpServer, image_resize::{self, ImgResizeError}, obj_storage_client::{MediaStorageClient, StoredData}};
-use crate::app_metrics::setup_metrics_recorder;
+use crate::{
+    api_error::ApiError,
+    app_metrics::MET_BYTES_SERVED,
+    configs::{HttpServer, PreviewMode},
+    http_layers,
+    image_resize::{self, ImgResizeError},
+    media_store::{MediaMetadata, MediaStore, OriginalsArchive, StorageError, StoredData},
+    string_util::StrUtil,
+};
 
 const IMG_MAX_SIZE: u32 = 400;
 
 #[derive(Clone)]
 struct EndpointSharedData {
-    media_storage_client: Arc<MediaStorageClient>,
+    media_storage_client: Arc<dyn MediaStore + Send + Sync>,
+    originals: Option<Arc<OriginalsArchive>>,
+    http_cfg: Arc<HttpServer>,
 }
 
 /// Creates an HTTP server that provides asset previews to clients
-pub async fn run_img_server(cfg: &HttpServer, media_storage_client: Arc<MediaStorageClient>) -> anyhow::Result<()> {
-    let recorder_handle = setup_metrics_recorder();
+/// Internal endpoints, like metrics and health checks, are served by [crate::admin_endpoints].
+pub async fn run_img_server(
+    cfg: &HttpServer,
+    media_storage_client: Arc<dyn MediaStore + Send + Sync>,
+    originals: Option<Arc<OriginalsArchive>>,
+) -> anyhow::Result<()> {
+    let app = router(cfg, media_storage_client, originals);
+
+    let port = cfg.port;
+    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{port}")).await?;
+    tracing::info!(port, "Preview HTTP server is listening");
+    axum::serve(listener, app).await?;
 
-    let state = EndpointSharedData { media_storage_client };
+    Ok(())
+}
 
-    let app = Router::new()
+fn router(
+    cfg: &HttpServer,
+    media_storage_client: Arc<dyn MediaStore + Send + Sync>,
+    originals: Option<Arc<OriginalsArchive>>,
+) -> Router {
+    let state = EndpointSharedData { media_storage_client, originals: originals.clone(), http_cfg: Arc::new(cfg.clone()) };
+    let mut app = Router::new()
         .route("/", get(root))
         .route("/preview/:id", get(get_asset))
-        .route("/metrics", get(move || { ready(recorder_handle.render())}))
+        .route("/asset/:id/info", get(get_asset_info))
         .with_state(state);
-
-    let port = cfg.port;
-    let listener = tokio::net::TcpListener::bind(format!("0.0.0.0:{port}")).await.unwrap();
-    axum::serve(listener, app).await.unwrap();
-
-    Ok(())
+    if let Some(originals) = originals {
+        app = app.merge(Router::new().route("/original/:id", get(get_original)).with_state(originals));
+    }
+    http_layers::with_middleware(
+        app,
+        Duration::from_secs(cfg.request_timeout_secs),
+        cfg.max_request_body_bytes,
+    )
 }
 
 async fn root() -> &'static str {
@@ -47,44 +76,177 @@ async fn root() -> &'static str {
 /// http://media-server/asset/XXXX?size=300
 /// If the requested size if bigger than the size of the image in the storage,
 /// then the resizing is ommited.
+///
+/// In the redirect mode, requests that don't need resizing are answered
+/// with a redirect to the object storage (or CDN) instead of streaming the bytes.
 async fn get_asset(
     Path(id): Path<String>,
     Query(params): Query<HashMap<String, String>>,
     state: State<EndpointSharedData>
-) -> Result<Resp, StatusCode> {
+) -> Result<Response, ApiError> {
     let size_op = params.get("size")
         .and_then(|s|s.parse::<u32>().ok())
         .filter(|&s| s < IMG_MAX_SIZE);
 
-    let start = Instant::now();
+    if size_op.is_none() && state.http_cfg.preview_mode == PreviewMode::Redirect {
+        if let Some(location) = redirect_location(&id, &state).await? {
+            metrics::counter!("get_preview_redirects_number").increment(1);
+            return Ok((StatusCode::FOUND, [(LOCATION, location)]).into_response());
+        }
+    }
 
     let prview = state.media_storage_client.get_media(&id).await;
-    metrics::counter!("storage_reads_total_time").increment(start.elapsed().as_millis() as u64);
-    metrics::counter!("storage_reads_number").increment(1);
 
-    let response = match prview {
-        Ok(StoredData {mime, bytes: byte_stream}) => {
+    match prview {
+        Ok(StoredData {mime, bytes: byte_stream, size: stored_size, ..}) => {
             match size_op {
                 Some(size) => {
-                    let bytes = byte_stream.collect().await.unwrap().into_bytes(); // fail on S3 error
-                    match image_resize::resize_fast(&bytes, size) {
-                        Ok(resized)                => Ok(Resp(mime, Body::from(resized))),
-                        Err(ImgResizeError::NoResizeNeeded) => Ok(Resp(mime, Body::from(bytes))),
-                        Err(_)                              => Err(StatusCode::INTERNAL_SERVER_ERROR),
+                    match byte_stream.collect().await.map(|b| b.into_bytes()) {
+                        Ok(bytes) => match image_resize::resize_fast(&bytes, size) {
+                            Ok(resized)                => Ok(Resp(mime, served(resized.into())).into_response()),
+                            Err(ImgResizeError::NoResizeNeeded) => Ok(Resp(mime, served(bytes)).into_response()),
+                            Err(err)                            => Err(ApiError::Internal(err.to_string())),
+                        },
+                        Err(err) => Err(StorageError::from(err).into()),
                     }
                 },
                 None => {
+                    if let Some(stored_size) = stored_size {
+                        metrics::counter!(MET_BYTES_SERVED).increment(stored_size);
+                    }
                     let asset_stream = ReaderStream::new(byte_stream.into_async_read());
-                    Ok(Resp(mime, Body::from_stream(asset_stream)))
+                    Ok(Resp(mime, Body::from_stream(asset_stream)).into_response())
                 },
             }
         },
-        Err(_) => Err(StatusCode::NOT_FOUND),
-    };
-    metrics::counter!("get_preview_requests_total_time").increment(start.elapsed().as_millis() as u64);
-    metrics::counter!("get_preview_requests_number").increment(1);
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// Provides the original asset as it has been downloaded, if originals are archived.
+///
+/// Originals are arbitrary bytes from NFT metadata, e.g. SVG with scripts, so browsers are told
+/// to download them instead of rendering them within the service origin.
+async fn get_original(
+    Path(id): Path<String>,
+    State(originals): State<Arc<OriginalsArchive>>,
+) -> Result<Response, ApiError> {
+    let StoredData { bytes, mime, size, .. } = originals.get(&id).await?;
+    if let Some(size) = size {
+        metrics::counter!(MET_BYTES_SERVED).increment(size);
+    }
+    let headers = [
+        (X_CONTENT_TYPE_OPTIONS, "nosniff"),
+        (CONTENT_SECURITY_POLICY, "sandbox"),
+        (CONTENT_DISPOSITION, "attachment"),
+    ];
+    Ok((headers, Resp(mime, Body::from_stream(ReaderStream::new(bytes.into_async_read())))).into_response())
+}
+
+fn served(bytes: Bytes) -> Body {
+    metrics::counter!(MET_BYTES_SERVED).increment(bytes.len() as u64);
+    Body::from(bytes)
+}
+
+/// CDN URL if configured, or a short-lived presigned storage URL otherwise.
+/// `None` if the storage cannot be accessed by clients directly, then the preview is proxied.
+async fn redirect_location(id: &str, state: &EndpointSharedData) -> Result<Option<String>, ApiError> {
+    match &state.http_cfg.cdn_base_url {
+        Some(cdn_base_url) => {
+            let key = state.media_storage_client.media_key(id).await?;
+            Ok(Some(format!("{}/{}", cdn_base_url.as_str().trim_right_slash(), key)))
+        },
+        None => {
+            let ttl = Duration::from_secs(state.http_cfg.presigned_url_ttl_secs);
+            Ok(state.media_storage_client.presigned_media_url(id, ttl).await?)
+        },
+    }
+}
+
+#[derive(Serialize)]
+struct AssetInfo {
+    id: String,
+    /// `None` for previews stored before the processing metadata was introduced
+    original: Option<OriginalInfo>,
+    processing: Option<ProcessingInfo>,
+    renditions: Vec<RenditionInfo>,
+}
 
-    response
+#[derive(Serialize)]
+struct OriginalInfo {
+    url: String,
+    mime: String,
+    size: u64,
+    width: Option<u32>,
+    height: Option<u32>,
+}
+
+#[derive(Serialize)]
+struct ProcessingInfo {
+    version: u32,
+    /// Unix timestamp (seconds)
+    processed_at: u64,
+}
+
+#[derive(Serialize)]
+struct RenditionInfo {
+    name: &'static str,
+    key: String,
+    content_type: String,
+    size: Option<u64>,
+    width: Option<u32>,
+    height: Option<u32>,
+    encoder: Option<String>,
+}
+
+/// Describes what is stored for the asset: the renditions and how they have been produced.
+/// Only object attributes are read, the content is not downloaded.
+/// The archived original is listed as a rendition too, if originals are archived.
+async fn get_asset_info(
+    Path(id): Path<String>,
+    state: State<EndpointSharedData>
+) -> Result<Json<AssetInfo>, ApiError> {
+    let preview = state.media_storage_client.head_media(&id).await?;
+    let metadata = MediaMetadata::from_object_metadata(&preview.metadata);
+
+    let mut renditions = vec![RenditionInfo {
+        name: "preview",
+        key: preview.key,
+        content_type: preview.mime,
+        size: preview.size,
+        width: metadata.as_ref().and_then(|m| m.preview_width),
+        height: metadata.as_ref().and_then(|m| m.preview_height),
+        encoder: metadata.as_ref().map(|m| m.encoder.clone()),
+    }];
+    if let Some(originals) = &state.originals {
+        match originals.head(&id).await {
+            Ok(archived) => renditions.push(RenditionInfo {
+                name: "original",
+                key: archived.key,
+                content_type: archived.mime,
+                size: archived.size,
+                width: metadata.as_ref().and_then(|m| m.original_width),
+                height: metadata.as_ref().and_then(|m| m.original_height),
+                encoder: None,
+            }),
+            // Not archived, e.g. too large for its class or downloaded before archiving was enabled
+            Err(StorageError::NotFound) => (),
+            Err(err) => return Err(err.into()),
+        }
+    }
+    let original = metadata.as_ref().map(|m| OriginalInfo {
+        url: m.original_url.clone(),
+        mime: m.original_mime.clone(),
+        size: m.original_size,
+        width: m.original_width,
+        height: m.original_height,
+    });
+    let processing = metadata.as_ref().map(|m| ProcessingInfo {
+        version: m.processing_version,
+        processed_at: m.processed_at,
+    });
+
+    Ok(Json(AssetInfo { id, original, processing, renditions }))
 }
 
 struct Resp(String, Body);
@@ -92,9 +254,108 @@ struct Resp(String, Body);
 impl IntoResponse for Resp {
     fn into_response(self) -> Response {
         let Resp(mime, body) = self;
-        Response::builder()
-            .header(CONTENT_TYPE, mime)
-            .body(body)
-            .unwrap()
+        // An invalid content type yields 500 response instead of a panic
+        ([(CONTENT_TYPE, mime)], body).into_response()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::{body::to_bytes, http::Request};
+    use tower::ServiceExt;
+
+    use crate::{configs::OriginalsCfg, media_store::{test_metadata, MemoryMediaStore}};
+
+    fn http_cfg() -> HttpServer {
+        HttpServer {
+            enabled: true,
+            port: 0,
+            preview_mode: PreviewMode::Proxy,
+            presigned_url_ttl_secs: 60,
+            cdn_base_url: None,
+            request_timeout_secs: 5,
+            max_request_body_bytes: 1024,
+        }
+    }
+
+    async fn get_json(app: Router, uri: &str) -> (StatusCode, serde_json::Value) {
+        let resp = app.oneshot(Request::get(uri).body(Body::empty()).unwrap()).await.unwrap();
+        let status = resp.status();
+        let body = to_bytes(resp.into_body(), usize::MAX).await.unwrap();
+        (status, serde_json::from_slice(&body).unwrap())
     }
-}
\ No newline at end of file
+
+    #[tokio::test]
+    async fn test_asset_info() {
+        let store = Arc::new(MemoryMediaStore::default());
+        let metadata = test_metadata("https://example.com/1.png", 1);
+        store.save_media("asset1", Bytes::from_static(b"webp").into(), "image/webp", &metadata).await.unwrap();
+        store.put("media/legacy", Bytes::from_static(b"png").into(), "image/png", &Default::default()).await.unwrap();
+        let app = router(&http_cfg(), store, None);
+
+        let (status, info) = get_json(app.clone(), "/asset/asset1/info").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(info["original"]["url"], "https://example.com/1.png");
+        assert_eq!(info["original"]["width"], 800);
+        assert_eq!(info["processing"]["processed_at"], 1_700_000_000);
+        assert_eq!(info["renditions"][0]["key"], "media/asset1");
+        assert_eq!(info["renditions"][0]["content_type"], "image/webp");
+        assert_eq!(info["renditions"][0]["size"], 4);
+        assert_eq!(info["renditions"][0]["width"], 400);
+
+        let (status, info) = get_json(app.clone(), "/asset/legacy/info").await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(info["original"].is_null());
+        assert_eq!(info["renditions"][0]["size"], 3);
+
+        let (status, info) = get_json(app, "/asset/missing/info").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(info["error"], "not_found");
+    }
+
+    #[tokio::test]
+    async fn test_asset_info_lists_original() {
+        let store: Arc<dyn MediaStore + Send + Sync> = Arc::new(MemoryMediaStore::default());
+        let originals = Arc::new(OriginalsArchive::new(store.clone(), OriginalsCfg { enabled: true, ..Default::default() }));
+        let metadata = test_metadata("https://example.com/1.png", 1);
+        store.save_media("asset1", Bytes::from_static(b"webp").into(), "image/webp", &metadata).await.unwrap();
+        store.save_media("asset2", Bytes::from_static(b"webp").into(), "image/webp", &metadata).await.unwrap();
+        let mime = crate::media_type::Mime::from_mime_str("image/png");
+        originals.archive("asset1", "https://example.com/1.png", &Bytes::from_static(b"png"), &mime).await;
+        let app = router(&http_cfg(), store, Some(originals));
+
+        let (status, info) = get_json(app.clone(), "/asset/asset1/info").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(info["renditions"][1]["name"], "original");
+        assert_eq!(info["renditions"][1]["key"], "originals/asset1");
+        assert_eq!(info["renditions"][1]["content_type"], "image/png");
+        assert_eq!(info["renditions"][1]["size"], 3);
+        assert_eq!(info["renditions"][1]["width"], 800);
+
+        let (status, info) = get_json(app, "/asset/asset2/info").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(info["renditions"].as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_original() {
+        let store: Arc<dyn MediaStore + Send + Sync> = Arc::new(MemoryMediaStore::default());
+        let originals = Arc::new(OriginalsArchive::new(store.clone(), OriginalsCfg { enabled: true, ..Default::default() }));
+        let mime = crate::media_type::Mime::from_mime_str("image/png");
+        originals.archive("asset1", "https://example.com/1.png", &Bytes::from_static(b"png"), &mime).await;
+
+        let app = router(&http_cfg(), store.clone(), Some(originals));
+        let resp = app.oneshot(Request::get("/original/asset1").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers()[CONTENT_TYPE], "image/png");
+        assert_eq!(resp.headers()[X_CONTENT_TYPE_OPTIONS], "nosniff");
+        assert_eq!(resp.headers()[CONTENT_SECURITY_POLICY], "sandbox");
+        assert_eq!(resp.headers()[CONTENT_DISPOSITION], "attachment");
+        assert_eq!(to_bytes(resp.into_body(), usize::MAX).await.unwrap().as_ref(), b"png");
+
+        let (status, body) = get_json(router(&http_cfg(), store, None), "/original/asset1").await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        assert_eq!(body["error"], "route_not_found");
+    }
+}
@@ -0,0 +1,174 @@
+//! Tower middleware stack that wraps the service HTTP endpoints.
+//!
+//! From outside in, each request gets:
+//! * `x-request-id` (taken from the request or generated) that is returned back in the response
+//! * an access log record, emitted within a span that carries the request ID
+//!   and continues the trace from the `traceparent` header, if present
+//! * request latency metrics, labelled by route and response status
+//! * panic catching, so a panicking handler results in 500 response instead of a dropped connection
+//! * request processing timeout
+//! * request body size limit
+use std::{any::Any, time::{Duration, Instant}};
+
+use axum::{
+    body::Body, error_handling::HandleErrorLayer, extract::{MatchedPath, Request},
+    middleware::{self, Next}, response::{IntoResponse, Response}, BoxError, Router
+};
+use http::StatusCode;
+use tower::ServiceBuilder;
+use tower_http::{
+    catch_panic::CatchPanicLayer,
+    limit::RequestBodyLimitLayer,
+    request_id::{MakeRequestUuid, PropagateRequestIdLayer, RequestId, SetRequestIdLayer},
+    trace::TraceLayer,
+};
+use tracing::Span;
+
+use crate::{
+    api_error::ApiError,
+    app_metrics::{CAT_ROUTE, CAT_STATUS, MET_HTTP_REQUEST_DURATION},
+    telemetry,
+};
+
+/// Wraps all the routes of the given router into the middleware stack
+pub fn with_middleware(router: Router, request_timeout: Duration, max_request_body_bytes: usize) -> Router {
+    let middleware = ServiceBuilder::new()
+        .layer(SetRequestIdLayer::x_request_id(MakeRequestUuid))
+        .layer(TraceLayer::new_for_http()
+            .make_span_with(make_request_span)
+            .on_request(())
+            .on_response(log_response)
+            .on_failure(()))
+        .layer(PropagateRequestIdLayer::x_request_id())
+        .layer(middleware::from_fn(record_request_metrics))
+        .layer(CatchPanicLayer::custom(handle_panic))
+        .layer(HandleErrorLayer::new(handle_middleware_error))
+        .timeout(request_timeout);
+
+    router
+        .fallback(|| async { ApiError::RouteNotFound })
+        .layer(RequestBodyLimitLayer::new(max_request_body_bytes))
+        .layer(middleware::map_response(body_limit_error_to_json))
+        .layer(middleware)
+}
+
+fn make_request_span(req: &Request<Body>) -> Span {
+    let request_id = req.extensions().get::<RequestId>()
+        .and_then(|id| id.header_value().to_str().ok())
+        .unwrap_or_default();
+
+    let span = tracing::info_span!(
+        "http_request",
+        method = %req.method(),
+        uri = %req.uri(),
+        request_id,
+    );
+    telemetry::continue_trace_from(req.headers(), &span);
+    span
+}
+
+fn log_response<B>(resp: &Response<B>, latency: Duration, _span: &Span) {
+    tracing::info!(
+        status = resp.status().as_u16(),
+        latency_ms = latency.as_millis() as u64,
+        "request finished"
+    );
+}
+
+async fn record_request_metrics(req: Request, next: Next) -> Response {
+    // Matched route pattern is used instead of the actual path to keep the labels cardinality low
+    let route = req.extensions().get::<MatchedPath>()
+        .map(|path| path.as_str().to_string())
+        .unwrap_or_else(|| "unmatched".to_string());
+
+    let start = Instant::now();
+    let resp = next.run(req).await;
+    metrics::histogram!(
+        MET_HTTP_REQUEST_DURATION,
+        CAT_ROUTE => route,
+        CAT_STATUS => resp.status().as_u16().to_string(),
+    ).record(start.elapsed().as_secs_f64());
+
+    resp
+}
+
+fn handle_panic(err: Box<dyn Any + Send + 'static>) -> Response {
+    let details = err.downcast_ref::<String>().map(String::as_str)
+        .or_else(|| err.downcast_ref::<&str>().copied())
+        .unwrap_or("unknown panic");
+    ApiError::Internal(format!("handler panicked: {details}")).into_response()
+}
+
+async fn handle_middleware_error(err: BoxError) -> ApiError {
+    if err.is::<tower::timeout::error::Elapsed>() {
+        ApiError::Timeout
+    } else {
+        ApiError::Internal(err.to_string())
+    }
+}
+
+/// [RequestBodyLimitLayer] responds with a plain text body, we want it to be consistent with other errors
+async fn body_limit_error_to_json(resp: Response) -> Response {
+    if resp.status() == StatusCode::PAYLOAD_TOO_LARGE {
+        ApiError::PayloadTooLarge.into_response()
+    } else {
+        resp
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use axum::routing::get;
+    use tower::ServiceExt;
+
+    fn test_router() -> Router {
+        let router = Router::new()
+            .route("/ok", get(|| async { "ok" }))
+            .route("/panic", get(panicking_handler))
+            .route("/slow", get(|| async { tokio::time::sleep(Duration::from_secs(5)).await; "late" }))
+            .route("/upload", axum::routing::post(|body: String| async move { body }));
+        with_middleware(router, Duration::from_secs(1), 16)
+    }
+
+    async fn panicking_handler() -> &'static str {
+        panic!("boom")
+    }
+
+    async fn call(req: Request<Body>) -> Response {
+        test_router().oneshot(req).await.unwrap()
+    }
+
+    fn is_json(resp: &Response) -> bool {
+        resp.headers().get(http::header::CONTENT_TYPE).unwrap() == "application/json"
+    }
+
+    #[tokio::test]
+    async fn test_request_id_is_propagated() {
+        let resp = call(Request::get("/ok").header("x-request-id", "abc").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::OK);
+        assert_eq!(resp.headers().get("x-request-id").unwrap(), "abc");
+
+        let resp = call(Request::get("/ok").body(Body::empty()).unwrap()).await;
+        assert!(resp.headers().get("x-request-id").is_some());
+    }
+
+    #[tokio::test]
+    async fn test_errors_are_rendered_as_json() {
+        let resp = call(Request::get("/panic").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(is_json(&resp));
+
+        let resp = call(Request::get("/slow").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::REQUEST_TIMEOUT);
+        assert!(is_json(&resp));
+
+        let resp = call(Request::get("/missing").body(Body::empty()).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::NOT_FOUND);
+        assert!(is_json(&resp));
+
+        let resp = call(Request::post("/upload").body(Body::from("x".repeat(100))).unwrap()).await;
+        assert_eq!(resp.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(is_json(&resp));
+    }
+}
@@ -1,8 +1,10 @@
 use bytes::Bytes;
 use fast_image_resize::{IntoImageView, PixelType, ResizeError};
-use image::{codecs::webp::WebPEncoder, io::Reader as ImageReader, ImageEncoder, ImageError, ImageFormat};
+use image::{codecs::webp::WebPEncoder, ImageEncoder, ImageReader, ImageError, ImageFormat};
 use thiserror::Error;
-use std::io::Cursor;
+use std::{io::Cursor, time::Instant};
+
+use crate::app_metrics::{CAT_PHASE, MET_IMAGE_PROCESSING_DURATION};
 
 #[derive(Error, Debug)]
 pub enum ImgResizeError {
@@ -18,11 +20,41 @@ pub enum ImgResizeError {
     NoResizeNeeded,
 }
 
+/// Image prepared for storing as a preview
+pub struct Preview {
+    /// WebP encoded image
+    pub bytes: Bytes,
+    pub width: u32,
+    pub height: u32,
+    pub original_width: u32,
+    pub original_height: u32,
+    /// How the bytes have been produced, see [ENCODER_WEBP_LOSSLESS] and [ENCODER_NONE]
+    pub encoder: &'static str,
+}
+
+/// Image is re-encoded to lossless WebP
+pub const ENCODER_WEBP_LOSSLESS: &str = "webp_lossless";
+/// Original image is stored as is, because it is already a small enough WebP
+pub const ENCODER_NONE: &str = "none";
+/// Content type of the previews
+pub const PREVIEW_MIME: &str = "image/webp";
+
 /// Resize given image to the given size
 /// ## Arguments:
 /// * `bytes` - bytes of image file
 /// * `biggest_size` - size of bounding box the image should be downscaled to
 pub fn resize_fast(bytes: &Bytes, biggest_size: u32) -> std::result::Result<Vec<u8>, ImgResizeError> {
+    let preview = make_preview(bytes, biggest_size)?;
+    if preview.encoder == ENCODER_NONE {
+        return Err(ImgResizeError::NoResizeNeeded);
+    }
+    Ok(preview.bytes.into())
+}
+
+/// Converts the given image to a WebP preview, that fits into `biggest_size` bounding box.
+/// Small WebP images are returned as is.
+pub fn make_preview(bytes: &Bytes, biggest_size: u32) -> std::result::Result<Preview, ImgResizeError> {
+    let start = Instant::now();
     let cursor = ImageReader::new(Cursor::new(bytes))
         .with_guessed_format()?;
 
@@ -31,15 +63,33 @@ pub fn resize_fast(bytes: &Bytes, biggest_size: u32) -> std::result::Result<Vec<
     };
 
     let img = cursor.decode()?;
+    record_phase("decode", start);
 
+    let (original_width, original_height) = (img.width(), img.height());
     let need_resizing = img.width() >= biggest_size || img.height() >= biggest_size;
     if !need_resizing {
         if format == ImageFormat::WebP {
-            return Err(ImgResizeError::NoResizeNeeded);
+            return Ok(Preview {
+                bytes: bytes.clone(),
+                width: original_width,
+                height: original_height,
+                original_width,
+                original_height,
+                encoder: ENCODER_NONE,
+            });
         }
+        let start = Instant::now();
         let mut result = Cursor::new(Vec::new());
         img.write_to(&mut result, ImageFormat::WebP)?;
-        return Ok(result.into_inner());
+        record_phase("encode", start);
+        return Ok(Preview {
+            bytes: result.into_inner().into(),
+            width: original_width,
+            height: original_height,
+            original_width,
+            original_height,
+            encoder: ENCODER_WEBP_LOSSLESS,
+        });
     }
 
     let (width, height) = {
@@ -56,9 +106,12 @@ pub fn resize_fast(bytes: &Bytes, biggest_size: u32) -> std::result::Result<Vec<
         height,
         img.pixel_type().unwrap_or(PixelType::U8x3),
     );
+    let start = Instant::now();
     let mut resizer = fast_image_resize::Resizer::new();
     resizer.resize(&img, &mut dst_image, None)?;
+    record_phase("resize", start);
     
+    let start = Instant::now();
     let mut result: Vec<u8> = Vec::new();
     WebPEncoder::new_lossless(&mut result)
         .write_image(
@@ -67,8 +120,20 @@ pub fn resize_fast(bytes: &Bytes, biggest_size: u32) -> std::result::Result<Vec<
             height,
             img.color().into(),
         )?;
+    record_phase("encode", start);
+
+    Ok(Preview {
+        bytes: result.into(),
+        width,
+        height,
+        original_width,
+        original_height,
+        encoder: ENCODER_WEBP_LOSSLESS,
+    })
+}
 
-    Ok(result)
+fn record_phase(phase: &'static str, start: Instant) {
+    metrics::histogram!(MET_IMAGE_PROCESSING_DURATION, CAT_PHASE => phase).record(start.elapsed().as_secs_f64());
 }
 
 
@@ -99,4 +164,16 @@ mod test {
 
         assert_eq!(new_format, ImageFormat::WebP);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_preview_dimensions() {
+        let data = std::fs::read("test_data/img/small.png").unwrap();
+        let bytes = Bytes::from(data);
+        let original = ImageReader::new(Cursor::new(&bytes)).with_guessed_format().unwrap().decode().unwrap();
+
+        let preview = make_preview(&bytes, 10).unwrap();
+        assert_eq!((preview.original_width, preview.original_height), (original.width(), original.height()));
+        assert_eq!(preview.width.max(preview.height), 10);
+        assert_eq!(preview.encoder, ENCODER_WEBP_LOSSLESS);
+    }
+}
@@ -1,29 +1,83 @@
-use configs::Settings;
+use clap::Parser;
+use cli::{Cli, Command};
+use configs::ConfigSource;
+use std::sync::Arc;
 
 mod grpc;
+mod cli;
 mod configs;
+mod config_reload;
 mod application;
 mod asset_processing;
-mod obj_storage_client;
+mod media_store;
 mod media_type;
 mod das_client;
 mod download;
 mod http_endpoints;
+mod admin_endpoints;
+mod http_layers;
+mod api_error;
+mod health;
+mod telemetry;
 mod string_util;
+mod takedown;
+mod url_filter;
 mod image_resize;
 mod app_metrics;
 
 use tracing::info;
-use tracing_subscriber;
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    tracing_subscriber::fmt::init();
+    let cli = Cli::parse();
+    let command = cli.command.unwrap_or(Command::Serve);
 
-    let app_config = Settings::for_env("local")?;
-    info!("Application config: {app_config:?}");
+    let config_source = ConfigSource::new(cli.env.as_deref(), cli.config_dir.as_deref());
+    let mut app_config = config_source.load()?;
 
-    application::App::start(&app_config).await;
+    if command == Command::CheckConfig {
+        println!("{app_config:#?}");
+        return Ok(());
+    }
+
+    let _telemetry_guard = telemetry::init(&app_config.logging, &app_config.tracing)?;
+    info!(profile = %app_config.env, ?command, "Application config: {app_config:?}");
+
+    // One-shot commands wait for the replication, the process exits right after them
+    if let Command::Takedown { id, url, reason } = &command {
+        let media_store = media_store::create_media_store(&app_config, media_store::QueueOverflow::Wait).await?;
+        let originals = media_store::create_originals_archive(&app_config, &media_store).await?;
+        let das_client = app_config.das.enabled.then(|| -> Arc<dyn das_client::DasClient + Send + Sync> {
+            Arc::new(das_client::UtilityChainClient { das_url: app_config.das.grpc_address.clone() })
+        });
+        let target = match (id, url) {
+            (Some(id), _) => takedown::TakedownTarget::Id(id.clone()),
+            (None, Some(url)) => takedown::TakedownTarget::Url(url.clone()),
+            (None, None) => unreachable!("Required by the CLI"),
+        };
+        let report = takedown::Takedown::new(media_store, originals, das_client).take_down(target, reason).await?;
+        info!(?report, "Asset has been taken down");
+        return Ok(());
+    }
+
+    if let Command::MigrateLayout { delete_source, dry_run } = command {
+        let media_store = media_store::create_media_store(&app_config, media_store::QueueOverflow::Wait).await?;
+        let stats = media_store::migrate_layout(media_store.as_ref(), delete_source, dry_run).await?;
+        media_store.flush().await?;
+        info!(?stats, dry_run, "Key layout migration is finished");
+        anyhow::ensure!(stats.failed == 0, "{} objects have not been migrated", stats.failed);
+        return Ok(());
+    }
+
+    // Reloaded config is compared with the one from the files, not with the command overrides
+    let reloadable = config_reload::spawn_config_reloader(config_source, app_config.clone())?;
+
+    if command == Command::Worker {
+        anyhow::ensure!(app_config.das.enabled, "Worker mode requires das.enabled = true");
+        app_config.http_server.enabled = false;
+    }
+
+    application::App::start(&app_config, reloadable).await?;
 
     Ok(())
 }
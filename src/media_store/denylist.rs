@@ -0,0 +1,48 @@
+use aws_sdk_s3::primitives::ByteStream;
+
+use super::{metadata, MediaStore, StorageError};
+
+/// Root of the denylist keys, next to the [super::KeyKind] roots
+const DENYLIST_ROOT: &str = "denylist";
+
+/// Content type of the denylist entries, they have no content, only metadata
+const DENYLIST_MIME: &str = "application/x-denylist-entry";
+
+/// What is denied by a denylist entry
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Denied {
+    /// URL, by its hash, i.e. the asset ID
+    Url,
+    /// Downloaded content, by its hash, whatever URL it comes from
+    Content,
+}
+
+impl Denied {
+    fn key(self, hash: &str) -> String {
+        let kind = match self {
+            Denied::Url => "url",
+            Denied::Content => "content",
+        };
+        format!("{DENYLIST_ROOT}/{kind}/{hash}")
+    }
+}
+
+/// Adds the entry to the denylist. The denylist is kept in the media store, so that all the instances see it.
+pub async fn deny(
+    store: &(dyn MediaStore + Send + Sync),
+    denied: Denied,
+    hash: &str,
+    reason: &str,
+    taken_down_at: u64,
+) -> Result<(), StorageError> {
+    let metadata = metadata::denylist_object_metadata(reason, taken_down_at);
+    store.put(&denied.key(hash), ByteStream::from_static(b""), DENYLIST_MIME, &metadata).await
+}
+
+pub async fn is_denied(store: &(dyn MediaStore + Send + Sync), denied: Denied, hash: &str) -> Result<bool, StorageError> {
+    match store.head(&denied.key(hash)).await {
+        Ok(_) => Ok(true),
+        Err(StorageError::NotFound) => Ok(false),
+        Err(err) => Err(err),
+    }
+}
@@ -0,0 +1,240 @@
+use std::{
+    io::ErrorKind,
+    path::{Component, Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use aws_sdk_s3::primitives::ByteStream;
+use serde::{Deserialize, Serialize};
+use tokio::time::Instant;
+
+use super::{record_operation, KeyLayout, KeysPage, MediaStore, ObjectInfo, ObjectMetadata, StorageError, StoredData};
+
+const META_FILE_SUFFIX: &str = ".meta.json";
+const TMP_FILE_MARKER: &str = ".tmp-";
+
+/// Stores objects as files under the root directory, for local development without S3.
+/// Object attributes (content type and metadata) are kept in a `<file>.meta.json` file next to the object.
+pub struct FsMediaStore {
+    root: PathBuf,
+    /// Used to make unique names of the temporary files
+    tmp_counter: AtomicU64,
+    key_layout: KeyLayout,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FileMeta {
+    content_type: String,
+    #[serde(default)]
+    metadata: ObjectMetadata,
+}
+
+impl FsMediaStore {
+    pub async fn new(root: impl AsRef<Path>, key_layout: KeyLayout) -> std::io::Result<FsMediaStore> {
+        let root = root.as_ref().to_path_buf();
+        tokio::fs::create_dir_all(&root).await?;
+        Ok(FsMediaStore { root, tmp_counter: AtomicU64::new(0), key_layout })
+    }
+
+    /// Object keys are relative paths, keys that would point outside of the root are rejected
+    fn path_for(&self, key: &str) -> Result<PathBuf, StorageError> {
+        let key_path = Path::new(key);
+        let is_valid = !key.is_empty()
+            && !key.ends_with(META_FILE_SUFFIX)
+            && key_path.components().all(|c| matches!(c, Component::Normal(_)));
+        if !is_valid {
+            return Err(StorageError::Other(format!("Invalid object key: {key}")));
+        }
+        Ok(self.root.join(key_path))
+    }
+
+    async fn read_meta(&self, path: &Path, key: &str) -> Result<FileMeta, StorageError> {
+        serde_json::from_slice(&tokio::fs::read(meta_path(path)).await?)
+            .map_err(|e| StorageError::Other(format!("Corrupted metadata of {key}: {e}")))
+    }
+
+    fn tmp_path_for(&self, path: &Path) -> PathBuf {
+        let n = self.tmp_counter.fetch_add(1, Ordering::Relaxed);
+        let mut tmp_name = path.file_name().unwrap_or_default().to_os_string();
+        tmp_name.push(format!("{TMP_FILE_MARKER}{}-{n}", std::process::id()));
+        path.with_file_name(tmp_name)
+    }
+
+    /// Keys of all the objects under the given directory (relative to the root)
+    async fn walk(&self, dir: &str) -> Result<Vec<String>, StorageError> {
+        let mut keys = Vec::new();
+        let mut dirs = vec![dir.to_string()];
+        while let Some(dir) = dirs.pop() {
+            let mut entries = match tokio::fs::read_dir(self.root.join(&dir)).await {
+                Ok(entries) => entries,
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            };
+            while let Some(entry) = entries.next_entry().await? {
+                let name = entry.file_name().to_string_lossy().to_string();
+                let key = if dir.is_empty() { name.clone() } else { format!("{dir}/{name}") };
+                if entry.file_type().await?.is_dir() {
+                    dirs.push(key);
+                } else if !name.ends_with(META_FILE_SUFFIX) && !name.contains(TMP_FILE_MARKER) {
+                    keys.push(key);
+                }
+            }
+        }
+        Ok(keys)
+    }
+
+    /// Writes the file atomically, so that readers never see a partially written object
+    async fn write_file(&self, path: &Path, mut content: impl tokio::io::AsyncRead + Unpin) -> std::io::Result<()> {
+        let tmp_path = self.tmp_path_for(path);
+        let result = async {
+            let mut file = tokio::fs::File::create(&tmp_path).await?;
+            tokio::io::copy(&mut content, &mut file).await?;
+            file.sync_all().await?;
+            tokio::fs::rename(&tmp_path, path).await
+        }.await;
+        if result.is_err() {
+            let _ = tokio::fs::remove_file(&tmp_path).await;
+        }
+        result
+    }
+}
+
+fn meta_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(META_FILE_SUFFIX);
+    path.with_file_name(name)
+}
+
+impl From<std::io::Error> for StorageError {
+    fn from(value: std::io::Error) -> Self {
+        match value.kind() {
+            ErrorKind::NotFound => StorageError::NotFound,
+            ErrorKind::PermissionDenied => StorageError::Forbidden,
+            _ => StorageError::Other(value.to_string()),
+        }
+    }
+}
+
+#[async_trait]
+impl MediaStore for FsMediaStore {
+    fn key_layout(&self) -> &KeyLayout {
+        &self.key_layout
+    }
+
+    #[tracing::instrument(name = "storage_get", skip(self))]
+    async fn get(&self, key: &str) -> Result<StoredData, StorageError> {
+        let start = Instant::now();
+        let result = async {
+            let path = self.path_for(key)?;
+            let meta = self.read_meta(&path, key).await?;
+            let size = tokio::fs::metadata(&path).await?.len();
+            let bytes = ByteStream::from_path(&path).await
+                .map_err(|e| StorageError::Other(e.to_string()))?;
+            Ok(StoredData { bytes, mime: meta.content_type, size: Some(size), metadata: meta.metadata })
+        }.await;
+        record_operation("get_object", start, &result);
+        result
+    }
+
+    #[tracing::instrument(name = "storage_head", skip(self))]
+    async fn head(&self, key: &str) -> Result<ObjectInfo, StorageError> {
+        let start = Instant::now();
+        let result = async {
+            let path = self.path_for(key)?;
+            let meta = self.read_meta(&path, key).await?;
+            let size = tokio::fs::metadata(&path).await?.len();
+            Ok(ObjectInfo { key: key.to_string(), mime: meta.content_type, size: Some(size), metadata: meta.metadata })
+        }.await;
+        record_operation("head_object", start, &result);
+        result
+    }
+
+    #[tracing::instrument(name = "storage_put", skip(self, byte_stream))]
+    async fn put(&self, key: &str, byte_stream: ByteStream, content_type: &str, metadata: &ObjectMetadata) -> Result<(), StorageError> {
+        let start = Instant::now();
+        let result = async {
+            let path = self.path_for(key)?;
+            if let Some(dir) = path.parent() {
+                tokio::fs::create_dir_all(dir).await?;
+            }
+            let meta = serde_json::to_vec(&FileMeta { content_type: content_type.to_string(), metadata: metadata.clone() })
+                .map_err(|e| StorageError::Other(e.to_string()))?;
+            // Metadata goes first, so that a readable object always has it
+            self.write_file(&meta_path(&path), meta.as_slice()).await?;
+            self.write_file(&path, byte_stream.into_async_read()).await?;
+            Ok(())
+        }.await;
+        record_operation("put_object", start, &result);
+        result
+    }
+
+    #[tracing::instrument(name = "storage_delete", skip(self))]
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        let path = self.path_for(key)?;
+        for path in [path.clone(), meta_path(&path)] {
+            match tokio::fs::remove_file(&path).await {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    /// Returns all the keys at once
+    async fn list(&self, prefix: &str, _continuation: Option<String>) -> Result<KeysPage, StorageError> {
+        // Only the directory the prefix points into has to be walked
+        let dir = prefix.rsplit_once('/').map(|(dir, _)| dir).unwrap_or_default();
+        let mut keys: Vec<String> = self.walk(dir).await?.into_iter()
+            .filter(|key| key.starts_with(prefix))
+            .collect();
+        keys.sort();
+        Ok(KeysPage { keys, next: None })
+    }
+
+    async fn check(&self) -> Result<(), StorageError> {
+        let metadata = tokio::fs::metadata(&self.root).await
+            .map_err(|e| StorageError::Unavailable(format!("{}: {e}", self.root.display())))?;
+        if !metadata.is_dir() {
+            return Err(StorageError::Unavailable(format!("{} is not a directory", self.root.display())));
+        }
+        Ok(())
+    }
+
+    async fn presigned_url(&self, _key: &str, _ttl: Duration) -> Result<Option<String>, StorageError> {
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_fs_store_roundtrip() {
+        let root = std::env::temp_dir().join(format!("media-files-store-fs-{}", std::process::id()));
+        let store = FsMediaStore::new(&root, KeyLayout::default()).await.unwrap();
+        store.check().await.unwrap();
+
+        let metadata = ObjectMetadata::from([("encoder".to_string(), "none".to_string())]);
+        store.put("media/asset1", ByteStream::from_static(b"image"), "image/png", &metadata).await.unwrap();
+        let stored = store.get_media("asset1").await.unwrap();
+        assert_eq!(stored.mime, "image/png");
+        assert_eq!(stored.size, Some(5));
+        assert_eq!(store.head_media("asset1").await.unwrap().metadata, metadata);
+        assert_eq!(stored.bytes.collect().await.unwrap().into_bytes().as_ref(), b"image");
+
+        assert!(matches!(store.get_media("asset2").await, Err(StorageError::NotFound)));
+        assert!(matches!(store.get("../outside").await, Err(StorageError::Other(_))));
+        assert!(matches!(store.get("/etc/passwd").await, Err(StorageError::Other(_))));
+
+        store.put("media/ab/asset2", ByteStream::from_static(b"image"), "image/png", &metadata).await.unwrap();
+        assert_eq!(store.list("media/", None).await.unwrap().keys, ["media/ab/asset2", "media/asset1"]);
+        store.delete("media/asset1").await.unwrap();
+        store.delete("media/asset1").await.unwrap();
+        assert_eq!(store.list("media/a", None).await.unwrap().keys, ["media/ab/asset2"]);
+
+        tokio::fs::remove_dir_all(&root).await.unwrap();
+    }
+}
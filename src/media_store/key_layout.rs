@@ -0,0 +1,168 @@
+use crate::configs::{KeyLayoutCfg, MediaStoreCfg};
+use super::PROCESSING_VERSION;
+
+/// What an object key points to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyKind {
+    /// Preview of an asset (or an alias of a [KeyKind::Content] object), addressed by the URL hash
+    Media,
+    /// Preview addressed by the hash of the original content
+    Content,
+}
+
+impl KeyKind {
+    pub fn root(self) -> &'static str {
+        match self {
+            KeyKind::Media => "media",
+            KeyKind::Content => "content",
+        }
+    }
+}
+
+/// Name of the only rendition we have for now
+const RENDITION_PREVIEW: &str = "preview";
+
+/// Builds object keys according to the configured layout, e.g. `media/v1/preview/ab/cd/{id}`
+/// with all the options enabled, or just `media/{id}` with none.
+///
+/// New objects are always written in the current layout, while reads also look at the keys
+/// of the previous processing versions and of the previous layout, if it's being migrated from.
+#[derive(Debug, Clone, Default)]
+pub struct KeyLayout {
+    current: KeyLayoutCfg,
+    previous: Option<KeyLayoutCfg>,
+}
+
+/// Object key split into its meaningful parts
+#[derive(Debug, PartialEq, Eq)]
+struct ParsedKey<'a> {
+    kind: KeyKind,
+    id: &'a str,
+    /// Processing version, for versioned layouts
+    version: Option<u32>,
+}
+
+impl KeyLayout {
+    pub fn new(cfg: &MediaStoreCfg) -> KeyLayout {
+        KeyLayout { current: cfg.key_layout, previous: cfg.previous_key_layout }
+    }
+
+    pub fn media_key(&self, asset_id: &str) -> String {
+        build_key(&self.current, KeyKind::Media, asset_id, PROCESSING_VERSION)
+    }
+
+    pub fn content_key(&self, content_hash: &str) -> String {
+        build_key(&self.current, KeyKind::Content, content_hash, PROCESSING_VERSION)
+    }
+
+    /// Keys the object may be stored under, in the order they should be looked up
+    pub fn read_keys(&self, kind: KeyKind, id: &str) -> Vec<String> {
+        let mut keys = Vec::new();
+        for layout in std::iter::once(&self.current).chain(&self.previous) {
+            let versions = if layout.versioned { 1 ..= PROCESSING_VERSION } else { PROCESSING_VERSION ..= PROCESSING_VERSION };
+            for version in versions.rev() {
+                let key = build_key(layout, kind, id, version);
+                if !keys.contains(&key) {
+                    keys.push(key);
+                }
+            }
+        }
+        keys
+    }
+
+    /// Aliases keep the full key of their target, which may have been moved to the current layout since
+    pub fn alias_target_keys(&self, target: &str) -> Vec<String> {
+        std::iter::once(target.to_string()).chain(self.migrated_key(target)).collect()
+    }
+
+    pub fn has_previous(&self) -> bool {
+        self.previous.is_some()
+    }
+
+    /// Key in the current layout for the key of the previous layout.
+    /// `None` if the key does not belong to the previous layout or does not change.
+    pub fn migrated_key(&self, key: &str) -> Option<String> {
+        let parsed = parse_key(self.previous.as_ref()?, key)?;
+        let version = parsed.version.unwrap_or(PROCESSING_VERSION);
+        let new_key = build_key(&self.current, parsed.kind, parsed.id, version);
+        (new_key != key).then_some(new_key)
+    }
+}
+
+fn build_key(layout: &KeyLayoutCfg, kind: KeyKind, id: &str, version: u32) -> String {
+    let mut key = kind.root().to_string();
+    if layout.versioned {
+        key.push_str(&format!("/v{version}"));
+    }
+    if layout.rendition_prefix {
+        key.push('/');
+        key.push_str(RENDITION_PREVIEW);
+    }
+    for level in 0 .. layout.shard_levels as usize {
+        key.push('/');
+        key.push_str(shard(id, level));
+    }
+    key.push('/');
+    key.push_str(id);
+    key
+}
+
+/// Reverse of [build_key], `None` if the key could not have been built with the given layout
+fn parse_key<'a>(layout: &KeyLayoutCfg, key: &'a str) -> Option<ParsedKey<'a>> {
+    let mut segments = key.split('/');
+    let kind = match segments.next()? {
+        "media" => KeyKind::Media,
+        "content" => KeyKind::Content,
+        _ => return None,
+    };
+    let version = if layout.versioned {
+        Some(segments.next()?.strip_prefix('v')?.parse().ok()?)
+    } else {
+        None
+    };
+    if layout.rendition_prefix && segments.next()? != RENDITION_PREVIEW {
+        return None;
+    }
+    let shards = (0 .. layout.shard_levels).map(|_| segments.next()).collect::<Option<Vec<_>>>()?;
+    let id = segments.next().filter(|id| !id.is_empty())?;
+    let is_valid = segments.next().is_none()
+        && shards.iter().enumerate().all(|(level, s)| *s == shard(id, level));
+    is_valid.then_some(ParsedKey { kind, id, version })
+}
+
+/// IDs are base58 strings, so slicing is safe for them, anything else goes to a catch-all shard
+fn shard(id: &str, level: usize) -> &str {
+    id.get(level * 2 .. level * 2 + 2).unwrap_or("_")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn layout(shard_levels: u8, versioned: bool, rendition_prefix: bool) -> KeyLayoutCfg {
+        KeyLayoutCfg { shard_levels, versioned, rendition_prefix }
+    }
+
+    #[test]
+    fn test_keys_are_built_and_parsed() {
+        assert_eq!(KeyLayout::default().media_key("abcdef"), "media/abcdef");
+
+        let full = layout(2, true, true);
+        let key = build_key(&full, KeyKind::Content, "abcdef", 3);
+        assert_eq!(key, "content/v3/preview/ab/cd/abcdef");
+        assert_eq!(parse_key(&full, &key), Some(ParsedKey { kind: KeyKind::Content, id: "abcdef", version: Some(3) }));
+
+        assert_eq!(parse_key(&full, "media/abcdef"), None);
+        assert_eq!(parse_key(&full, "media/v1/preview/ab/xx/abcdef"), None);
+        assert_eq!(parse_key(&layout(0, false, false), "media/ab/cd/abcdef"), None);
+        assert_eq!(parse_key(&layout(0, false, false), "originals/abcdef"), None);
+    }
+
+    #[test]
+    fn test_migration_from_previous_layout() {
+        let key_layout = KeyLayout { current: layout(1, false, false), previous: Some(layout(0, false, false)) };
+        assert_eq!(key_layout.migrated_key("media/abcdef").as_deref(), Some("media/ab/abcdef"));
+        assert_eq!(key_layout.migrated_key("media/ab/abcdef"), None);
+        assert_eq!(key_layout.read_keys(KeyKind::Media, "abcdef"), ["media/ab/abcdef", "media/abcdef"]);
+    }
+}
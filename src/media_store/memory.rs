@@ -0,0 +1,171 @@
+use std::{collections::HashMap, sync::RwLock, time::Duration};
+
+use async_trait::async_trait;
+use aws_sdk_s3::primitives::ByteStream;
+use bytes::Bytes;
+
+use super::{KeyLayout, KeysPage, MediaStore, ObjectInfo, ObjectMetadata, StorageError, StoredData};
+
+/// Keeps objects in memory, for tests and trying the service out.
+/// Everything is lost on restart.
+#[derive(Default)]
+pub struct MemoryMediaStore {
+    objects: RwLock<HashMap<String, MemoryObject>>,
+    key_layout: KeyLayout,
+}
+
+struct MemoryObject {
+    bytes: Bytes,
+    content_type: String,
+    metadata: ObjectMetadata,
+}
+
+impl MemoryMediaStore {
+    pub fn new(key_layout: KeyLayout) -> MemoryMediaStore {
+        MemoryMediaStore { objects: Default::default(), key_layout }
+    }
+}
+
+#[async_trait]
+impl MediaStore for MemoryMediaStore {
+    fn key_layout(&self) -> &KeyLayout {
+        &self.key_layout
+    }
+
+    async fn get(&self, key: &str) -> Result<StoredData, StorageError> {
+        let objects = self.objects.read().unwrap();
+        let object = objects.get(key).ok_or(StorageError::NotFound)?;
+        Ok(StoredData {
+            bytes: object.bytes.clone().into(),
+            mime: object.content_type.clone(),
+            size: Some(object.bytes.len() as u64),
+            metadata: object.metadata.clone(),
+        })
+    }
+
+    async fn head(&self, key: &str) -> Result<ObjectInfo, StorageError> {
+        let objects = self.objects.read().unwrap();
+        let object = objects.get(key).ok_or(StorageError::NotFound)?;
+        Ok(ObjectInfo {
+            key: key.to_string(),
+            mime: object.content_type.clone(),
+            size: Some(object.bytes.len() as u64),
+            metadata: object.metadata.clone(),
+        })
+    }
+
+    async fn put(&self, key: &str, byte_stream: ByteStream, content_type: &str, metadata: &ObjectMetadata) -> Result<(), StorageError> {
+        let bytes = byte_stream.collect().await?.into_bytes();
+        let object = MemoryObject { bytes, content_type: content_type.to_string(), metadata: metadata.clone() };
+        self.objects.write().unwrap().insert(key.to_string(), object);
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        self.objects.write().unwrap().remove(key);
+        Ok(())
+    }
+
+    async fn list(&self, prefix: &str, _continuation: Option<String>) -> Result<KeysPage, StorageError> {
+        let mut keys: Vec<String> = self.objects.read().unwrap().keys()
+            .filter(|key| key.starts_with(prefix))
+            .cloned()
+            .collect();
+        keys.sort();
+        Ok(KeysPage { keys, next: None })
+    }
+
+    async fn check(&self) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    async fn presigned_url(&self, _key: &str, _ttl: Duration) -> Result<Option<String>, StorageError> {
+        Ok(None)
+    }
+}
+
+/// Storage that has failed completely, for tests of the failure handling
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct UnavailableStore(KeyLayout);
+
+#[cfg(test)]
+#[async_trait]
+impl MediaStore for UnavailableStore {
+    fn key_layout(&self) -> &KeyLayout {
+        &self.0
+    }
+    async fn get(&self, _: &str) -> Result<StoredData, StorageError> {
+        Err(StorageError::Unavailable("down".to_string()))
+    }
+    async fn head(&self, _: &str) -> Result<ObjectInfo, StorageError> {
+        Err(StorageError::Unavailable("down".to_string()))
+    }
+    async fn put(&self, _: &str, _: ByteStream, _: &str, _: &ObjectMetadata) -> Result<(), StorageError> {
+        Err(StorageError::Unavailable("down".to_string()))
+    }
+    async fn delete(&self, _: &str) -> Result<(), StorageError> {
+        Err(StorageError::Unavailable("down".to_string()))
+    }
+    async fn list(&self, _: &str, _: Option<String>) -> Result<KeysPage, StorageError> {
+        Err(StorageError::Unavailable("down".to_string()))
+    }
+    async fn check(&self) -> Result<(), StorageError> {
+        Err(StorageError::Unavailable("down".to_string()))
+    }
+    async fn presigned_url(&self, _: &str, _: Duration) -> Result<Option<String>, StorageError> {
+        Ok(None)
+    }
+}
+
+/// Storage that can be read, but refuses writes, for tests of the failure handling
+#[cfg(test)]
+#[derive(Default)]
+pub(crate) struct ReadOnlyStore(MemoryMediaStore);
+
+#[cfg(test)]
+#[async_trait]
+impl MediaStore for ReadOnlyStore {
+    fn key_layout(&self) -> &KeyLayout {
+        self.0.key_layout()
+    }
+    async fn get(&self, key: &str) -> Result<StoredData, StorageError> {
+        self.0.get(key).await
+    }
+    async fn head(&self, key: &str) -> Result<ObjectInfo, StorageError> {
+        self.0.head(key).await
+    }
+    async fn put(&self, _: &str, _: ByteStream, _: &str, _: &ObjectMetadata) -> Result<(), StorageError> {
+        Err(StorageError::Forbidden)
+    }
+    async fn delete(&self, _: &str) -> Result<(), StorageError> {
+        Err(StorageError::Forbidden)
+    }
+    async fn list(&self, prefix: &str, continuation: Option<String>) -> Result<KeysPage, StorageError> {
+        self.0.list(prefix, continuation).await
+    }
+    async fn check(&self) -> Result<(), StorageError> {
+        self.0.check().await
+    }
+    async fn presigned_url(&self, key: &str, ttl: Duration) -> Result<Option<String>, StorageError> {
+        self.0.presigned_url(key, ttl).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_memory_store_roundtrip() {
+        let store = MemoryMediaStore::default();
+        store.put("media/asset1", Bytes::from_static(b"image").into(), "image/png", &ObjectMetadata::new()).await.unwrap();
+
+        let stored = store.get_media("asset1").await.unwrap();
+        assert_eq!(stored.mime, "image/png");
+        assert_eq!(stored.size, Some(5));
+        assert_eq!(stored.bytes.collect().await.unwrap().into_bytes().as_ref(), b"image");
+        assert!(matches!(store.get_media("asset2").await, Err(StorageError::NotFound)));
+        assert_eq!(store.presigned_media_url("asset1", Duration::from_secs(1)).await.unwrap(), None);
+    }
+}
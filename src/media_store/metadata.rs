@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+
+/// User-defined object metadata, stored along with the object (e.g. `x-amz-meta-*` headers in S3)
+pub type ObjectMetadata = HashMap<String, String>;
+
+/// S3 limits the total size of the user metadata to 2KB, long URLs are truncated to fit
+const MAX_URL_LEN: usize = 1024;
+
+/// Characters that cannot be used in HTTP header values as is
+const NON_HEADER_SAFE: &AsciiSet = &CONTROLS.add(b'%').add(b'"').add(b' ');
+
+const ORIGINAL_URL: &str = "original-url";
+const ORIGINAL_MIME: &str = "original-mime";
+const ORIGINAL_SIZE: &str = "original-size";
+const ORIGINAL_WIDTH: &str = "original-width";
+const ORIGINAL_HEIGHT: &str = "original-height";
+const PREVIEW_WIDTH: &str = "preview-width";
+const PREVIEW_HEIGHT: &str = "preview-height";
+const ENCODER: &str = "encoder";
+const PROCESSING_VERSION: &str = "processing-version";
+const PROCESSED_AT: &str = "processed-at";
+/// Key of the object an alias points to
+pub(super) const ALIAS_TARGET: &str = "alias-target";
+const TAKEDOWN_REASON: &str = "takedown-reason";
+const TAKEN_DOWN_AT: &str = "taken-down-at";
+
+/// Describes how a stored preview has been produced
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+pub struct MediaMetadata {
+    /// URL the asset has been downloaded from, truncated if too long
+    pub original_url: String,
+    pub original_mime: String,
+    /// Size of the downloaded asset in bytes
+    pub original_size: u64,
+    pub original_width: Option<u32>,
+    pub original_height: Option<u32>,
+    pub preview_width: Option<u32>,
+    pub preview_height: Option<u32>,
+    /// How the preview bytes have been produced, e.g. "webp_lossless"
+    pub encoder: String,
+    /// Version of the processing logic, see [super::PROCESSING_VERSION]
+    pub processing_version: u32,
+    /// Unix timestamp (seconds) of the processing
+    pub processed_at: u64,
+}
+
+/// Metadata of an archived original: the URL it has been downloaded from
+pub fn original_object_metadata(url: &str) -> ObjectMetadata {
+    ObjectMetadata::from([(ORIGINAL_URL.to_string(), encode_url(url))])
+}
+
+/// Metadata of a denylist entry: why and when (unix timestamp) the asset has been taken down
+pub fn denylist_object_metadata(reason: &str, taken_down_at: u64) -> ObjectMetadata {
+    ObjectMetadata::from([
+        (TAKEDOWN_REASON.to_string(), encode_url(reason)),
+        (TAKEN_DOWN_AT.to_string(), taken_down_at.to_string()),
+    ])
+}
+
+/// Percent-encodes the value, so that it fits into an HTTP header, and truncates it to [MAX_URL_LEN]
+fn encode_url(url: &str) -> String {
+    let mut url = utf8_percent_encode(url, NON_HEADER_SAFE).to_string();
+    if url.len() > MAX_URL_LEN {
+        // Cut at a char boundary, that is not inside of a percent-encoded sequence
+        let mut end = MAX_URL_LEN;
+        while !url.is_char_boundary(end) || url[.. end].rfind('%').is_some_and(|i| i + 3 > end) {
+            end -= 1;
+        }
+        url.truncate(end);
+    }
+    url
+}
+
+impl MediaMetadata {
+    pub fn to_object_metadata(&self) -> ObjectMetadata {
+        let mut metadata = ObjectMetadata::new();
+        metadata.insert(ORIGINAL_URL.to_string(), encode_url(&self.original_url));
+        metadata.insert(ORIGINAL_MIME.to_string(), self.original_mime.clone());
+        metadata.insert(ORIGINAL_SIZE.to_string(), self.original_size.to_string());
+        let optional = [
+            (ORIGINAL_WIDTH, self.original_width),
+            (ORIGINAL_HEIGHT, self.original_height),
+            (PREVIEW_WIDTH, self.preview_width),
+            (PREVIEW_HEIGHT, self.preview_height),
+        ];
+        for (name, value) in optional {
+            if let Some(value) = value {
+                metadata.insert(name.to_string(), value.to_string());
+            }
+        }
+        metadata.insert(ENCODER.to_string(), self.encoder.clone());
+        metadata.insert(PROCESSING_VERSION.to_string(), self.processing_version.to_string());
+        metadata.insert(PROCESSED_AT.to_string(), self.processed_at.to_string());
+        metadata
+    }
+
+    /// Returns `None` for objects stored without metadata, e.g. before it was introduced
+    pub fn from_object_metadata(metadata: &ObjectMetadata) -> Option<MediaMetadata> {
+        let get = |name: &str| metadata.get(name);
+        let parse_opt = |name: &str| get(name).and_then(|v| v.parse().ok());
+        Some(MediaMetadata {
+            original_url: percent_decode_str(get(ORIGINAL_URL)?).decode_utf8_lossy().to_string(),
+            original_mime: get(ORIGINAL_MIME)?.clone(),
+            original_size: get(ORIGINAL_SIZE)?.parse().ok()?,
+            original_width: parse_opt(ORIGINAL_WIDTH),
+            original_height: parse_opt(ORIGINAL_HEIGHT),
+            preview_width: parse_opt(PREVIEW_WIDTH),
+            preview_height: parse_opt(PREVIEW_HEIGHT),
+            encoder: get(ENCODER)?.clone(),
+            processing_version: get(PROCESSING_VERSION)?.parse().ok()?,
+            processed_at: get(PROCESSED_AT)?.parse().ok()?,
+        })
+    }
+}
+
+/// Metadata of a preview of the given URL, as if it has been produced by the given processing version
+#[cfg(test)]
+pub(crate) fn test_metadata(url: &str, processing_version: u32) -> MediaMetadata {
+    MediaMetadata {
+        original_url: url.to_string(),
+        original_mime: "image/png".to_string(),
+        original_size: 1024,
+        original_width: Some(800),
+        original_height: Some(600),
+        preview_width: Some(400),
+        preview_height: Some(300),
+        encoder: "webp_lossless".to_string(),
+        processing_version,
+        processed_at: 1_700_000_000,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_metadata_roundtrip() {
+        let meta = test_metadata("https://example.com/img/кот 1.png", 1);
+        let object_metadata = meta.to_object_metadata();
+        assert!(object_metadata.values().all(|v| v.is_ascii()));
+        assert_eq!(MediaMetadata::from_object_metadata(&object_metadata), Some(meta));
+
+        assert_eq!(MediaMetadata::from_object_metadata(&ObjectMetadata::new()), None);
+    }
+
+    #[test]
+    fn test_long_url_is_truncated() {
+        let url = format!("https://example.com/{}", "ж".repeat(1000));
+        let object_metadata = test_metadata(&url, 1).to_object_metadata();
+        assert!(object_metadata[ORIGINAL_URL].len() <= MAX_URL_LEN);
+
+        let restored = MediaMetadata::from_object_metadata(&object_metadata).unwrap();
+        assert!(url.starts_with(&restored.original_url));
+    }
+}
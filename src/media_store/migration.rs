@@ -0,0 +1,122 @@
+use aws_sdk_s3::primitives::ByteStream;
+
+use super::{metadata, KeyKind, MediaStore, StorageError, ALIAS_MIME};
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct MigrationStats {
+    pub migrated: u64,
+    /// Objects that are not in the previous layout, e.g. already migrated ones
+    pub skipped: u64,
+    pub failed: u64,
+}
+
+/// Moves objects from `media_store.previous_key_layout` to `media_store.key_layout`.
+///
+/// Content objects go first, so that migrated aliases never point to missing objects.
+/// Objects are copied, sources are deleted only with `delete_source`, so the migration can be repeated,
+/// e.g. to pick up objects written in the previous layout while it was running.
+pub async fn migrate_layout(
+    store: &(dyn MediaStore + Send + Sync),
+    delete_source: bool,
+    dry_run: bool,
+) -> Result<MigrationStats, StorageError> {
+    let layout = store.key_layout();
+    if !layout.has_previous() {
+        return Err(StorageError::Other("media_store.previous_key_layout is not set, nothing to migrate from".to_string()));
+    }
+
+    let mut stats = MigrationStats::default();
+    for kind in [KeyKind::Content, KeyKind::Media] {
+        let prefix = format!("{}/", kind.root());
+        let mut continuation = None;
+        loop {
+            let page = store.list(&prefix, continuation).await?;
+            for key in page.keys {
+                let Some(new_key) = layout.migrated_key(&key) else {
+                    stats.skipped += 1;
+                    continue;
+                };
+                if dry_run {
+                    tracing::info!(key, new_key, "Object would be migrated");
+                    stats.migrated += 1;
+                    continue;
+                }
+                match migrate_object(store, &key, &new_key, delete_source).await {
+                    Ok(()) => stats.migrated += 1,
+                    Err(err) => {
+                        tracing::warn!(key, new_key, error = %err, "Cannot migrate object");
+                        stats.failed += 1;
+                    },
+                }
+            }
+            tracing::info!(?stats, "Migration progress");
+            match page.next {
+                Some(next) => continuation = Some(next),
+                None => break,
+            }
+        }
+    }
+    Ok(stats)
+}
+
+async fn migrate_object(
+    store: &(dyn MediaStore + Send + Sync),
+    key: &str,
+    new_key: &str,
+    delete_source: bool,
+) -> Result<(), StorageError> {
+    let info = store.head(key).await?;
+    if info.mime == ALIAS_MIME {
+        // Alias target has been moved as well
+        let mut metadata = info.metadata;
+        let target = metadata.get(metadata::ALIAS_TARGET)
+            .ok_or_else(|| StorageError::Other(format!("Corrupted alias {key}")))?;
+        let target = store.key_layout().migrated_key(target).unwrap_or_else(|| target.clone());
+        metadata.insert(metadata::ALIAS_TARGET.to_string(), target.clone());
+        store.put(new_key, ByteStream::from(target.into_bytes()), ALIAS_MIME, &metadata).await?;
+    } else {
+        store.copy(key, new_key).await?;
+    }
+    if delete_source {
+        store.delete(key).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        configs::{KeyLayoutCfg, MediaStoreCfg},
+        media_store::{test_metadata, KeyLayout, MemoryMediaStore},
+    };
+
+    #[tokio::test]
+    async fn test_objects_are_migrated() {
+        let flat = KeyLayoutCfg::default();
+        let sharded = KeyLayoutCfg { shard_levels: 1, ..Default::default() };
+        let old_store = MemoryMediaStore::new(KeyLayout::default());
+        old_store.save_media("asset1", ByteStream::from_static(b"one"), "image/webp", &test_metadata("https://a/1.png", 1)).await.unwrap();
+        old_store.save_content("hash2", ByteStream::from_static(b"two"), "image/webp", &test_metadata("https://a/1.png", 1)).await.unwrap();
+        old_store.save_alias("asset2", "hash2", &test_metadata("https://a/1.png", 1)).await.unwrap();
+
+        // Same objects, seen through the new layout
+        let cfg = MediaStoreCfg { key_layout: sharded, previous_key_layout: Some(flat), ..Default::default() };
+        let store = MemoryMediaStore::new(KeyLayout::new(&cfg));
+        for key in old_store.list("", None).await.unwrap().keys {
+            let info = old_store.head(&key).await.unwrap();
+            store.put(&key, old_store.get(&key).await.unwrap().bytes, &info.mime, &info.metadata).await.unwrap();
+        }
+
+        assert_eq!(migrate_layout(&store, false, true).await.unwrap(), MigrationStats { migrated: 3, skipped: 0, failed: 0 });
+        assert_eq!(store.list("", None).await.unwrap().keys.len(), 3);
+
+        let stats = migrate_layout(&store, true, false).await.unwrap();
+        assert_eq!(stats, MigrationStats { migrated: 3, skipped: 0, failed: 0 });
+        assert_eq!(store.list("", None).await.unwrap().keys, ["content/ha/hash2", "media/as/asset1", "media/as/asset2"]);
+        assert_eq!(store.head_media("asset2").await.unwrap().key, "content/ha/hash2");
+        assert_eq!(store.get_media("asset2").await.unwrap().bytes.collect().await.unwrap().into_bytes().as_ref(), b"two");
+
+        assert_eq!(migrate_layout(&store, true, false).await.unwrap(), MigrationStats { migrated: 0, skipped: 3, failed: 0 });
+    }
+}
@@ -0,0 +1,323 @@
+//! Storage of the asset previews.
+//!
+//! [MediaStore] is implemented by S3 (production), local filesystem (local development)
+//! and in-memory (tests) backends, the backend is selected by the `media_store` config section.
+//!
+//! With `obj_storage_replicas`, the S3 store is wrapped into [ReplicatedMediaStore].
+//!
+//! Object keys are built by [KeyLayout].
+//! Previews of byte-identical images are stored once, under the content hash ([KeyLayout::content_key]).
+//! The asset key ([KeyLayout::media_key]) then holds a tiny alias object, that points to the content key.
+//! Asset-level operations follow aliases, so it makes no difference for the callers
+//! whether a preview has been stored directly or via an alias.
+use std::{sync::Arc, time::Duration};
+
+use anyhow::Context;
+use async_trait::async_trait;
+use aws_sdk_s3::primitives::ByteStream;
+use thiserror::Error;
+use tokio::time::Instant;
+
+use crate::{
+    app_metrics::{outcome, CAT_OPERATION, CAT_OUTCOME, MET_BYTES_STORED, MET_STORAGE_OPERATION_DURATION},
+    configs::{MediaStoreBackend, ObjStorage, ReplicaRole, Settings},
+};
+
+mod denylist;
+mod fs;
+mod key_layout;
+mod memory;
+mod metadata;
+mod migration;
+mod originals;
+mod replicated;
+mod s3;
+mod s3_credentials;
+mod s3_multipart;
+mod self_check;
+
+pub use denylist::{deny, is_denied, Denied};
+pub use fs::FsMediaStore;
+pub use key_layout::{KeyKind, KeyLayout};
+pub use memory::MemoryMediaStore;
+#[cfg(test)]
+pub(crate) use memory::{ReadOnlyStore, UnavailableStore};
+pub use metadata::{MediaMetadata, ObjectMetadata};
+#[cfg(test)]
+pub(crate) use metadata::test_metadata;
+pub use migration::migrate_layout;
+pub use originals::{create_originals_archive, OriginalsArchive};
+pub use replicated::{QueueOverflow, Replica, ReplicatedMediaStore};
+pub use s3::S3MediaStore;
+pub use self_check::self_check;
+
+/// Version of the processing logic, stored in the preview metadata and in the versioned keys.
+/// Should be increased whenever the way previews are produced changes.
+pub const PROCESSING_VERSION: u32 = 1;
+
+/// Content type of the alias objects, their content is the key of the object they point to
+const ALIAS_MIME: &str = "application/x-media-alias";
+
+/// Represents object storage failure, classified by what the caller can do about it
+#[derive(Error, Debug)]
+pub enum StorageError {
+    #[error("Object not found")]
+    NotFound,
+    #[error("Access to the storage is forbidden")]
+    Forbidden,
+    /// Storage cannot be reached right now, we probably just need to try again later
+    #[error("Storage is unavailable: {0}")]
+    Unavailable(String),
+    #[error("Storage error: {0}")]
+    Other(String),
+}
+
+impl From<aws_sdk_s3::primitives::ByteStreamError> for StorageError {
+    fn from(value: aws_sdk_s3::primitives::ByteStreamError) -> Self {
+        StorageError::Unavailable(value.to_string())
+    }
+}
+
+pub struct StoredData {
+    pub bytes: ByteStream,
+    pub mime: String,
+    /// Object size in bytes, if known
+    pub size: Option<u64>,
+    /// Read along with the content, so that they always belong to the same version of the object
+    pub metadata: ObjectMetadata,
+}
+
+/// Object attributes, without its content
+pub struct ObjectInfo {
+    /// Key, the object is stored under. For aliases, it's the key of the object the alias points to.
+    pub key: String,
+    pub mime: String,
+    pub size: Option<u64>,
+    pub metadata: ObjectMetadata,
+}
+
+/// Page of the object keys, `next` is the continuation token of the next page
+pub struct KeysPage {
+    pub keys: Vec<String>,
+    pub next: Option<String>,
+}
+
+/// Key-value storage for the asset previews.
+///
+/// Backends implement only the key-based primitives,
+/// the asset-level operations are provided on top of them.
+#[async_trait]
+pub trait MediaStore {
+    /// Layout of the keys of the asset-level operations
+    fn key_layout(&self) -> &KeyLayout;
+
+    /// Reads the object stored under the given key
+    async fn get(&self, key: &str) -> Result<StoredData, StorageError>;
+
+    /// Reads attributes of the object stored under the given key
+    async fn head(&self, key: &str) -> Result<ObjectInfo, StorageError>;
+
+    /// Stores the object with the given metadata under the given key, replacing the existing one
+    async fn put(&self, key: &str, byte_stream: ByteStream, content_type: &str, metadata: &ObjectMetadata) -> Result<(), StorageError>;
+
+    /// Deletes the object, deleting a missing object is not an error
+    async fn delete(&self, key: &str) -> Result<(), StorageError>;
+
+    /// Lists keys of the objects starting with the given prefix, page by page.
+    /// `continuation` is the `next` token of the previous page.
+    async fn list(&self, prefix: &str, continuation: Option<String>) -> Result<KeysPage, StorageError>;
+
+    /// Copies the object along with its content type and metadata
+    async fn copy(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        let stored = self.get(from).await?;
+        self.put(to, stored.bytes, &stored.mime, &stored.metadata).await
+    }
+
+    /// Checks that the storage is reachable and accessible
+    async fn check(&self) -> Result<(), StorageError>;
+
+    /// Waits until the writes made so far are complete, e.g. replicated to the secondary storages.
+    /// Short-lived processes have to call it before exiting, since the background work is lost otherwise.
+    async fn flush(&self) -> Result<(), StorageError> {
+        Ok(())
+    }
+
+    /// Generates a short-lived URL that allows to download the object directly from the storage.
+    /// Returns `None` if the storage cannot be accessed by clients directly.
+    async fn presigned_url(&self, key: &str, ttl: Duration) -> Result<Option<String>, StorageError>;
+
+    async fn get_media(&self, id: &str) -> Result<StoredData, StorageError> {
+        let stored = self.get_first(&self.key_layout().read_keys(KeyKind::Media, id)).await?;
+        if stored.mime != ALIAS_MIME {
+            return Ok(stored);
+        }
+        let target = stored.bytes.collect().await?.into_bytes();
+        let target = std::str::from_utf8(&target)
+            .map_err(|_| StorageError::Other(format!("Corrupted alias of {id}")))?;
+        self.get_first(&self.key_layout().alias_target_keys(target)).await
+    }
+
+    /// Returns attributes of the stored preview along with the metadata of the given asset,
+    /// which may differ from the metadata of the preview if it is shared by several assets
+    async fn head_media(&self, id: &str) -> Result<ObjectInfo, StorageError> {
+        let mut info = self.head_first(&self.key_layout().read_keys(KeyKind::Media, id)).await?;
+        if info.mime != ALIAS_MIME {
+            return Ok(info);
+        }
+        let target = info.metadata.remove(metadata::ALIAS_TARGET)
+            .ok_or_else(|| StorageError::Other(format!("Corrupted alias of {id}")))?;
+        let target = self.head_first(&self.key_layout().alias_target_keys(&target)).await?;
+        Ok(ObjectInfo { metadata: info.metadata, ..target })
+    }
+
+    /// Reads the first of the given keys, that exists
+    async fn get_first(&self, keys: &[String]) -> Result<StoredData, StorageError> {
+        for key in keys {
+            match self.get(key).await {
+                Err(StorageError::NotFound) => continue,
+                result => return result,
+            }
+        }
+        Err(StorageError::NotFound)
+    }
+
+    async fn head_first(&self, keys: &[String]) -> Result<ObjectInfo, StorageError> {
+        for key in keys {
+            match self.head(key).await {
+                Err(StorageError::NotFound) => continue,
+                result => return result,
+            }
+        }
+        Err(StorageError::NotFound)
+    }
+
+    /// Key of the object, that holds the preview of the given asset
+    async fn media_key(&self, id: &str) -> Result<String, StorageError> {
+        Ok(self.head_media(id).await?.key)
+    }
+
+    async fn save_media(
+        &self,
+        id: &str,
+        byte_stream: ByteStream,
+        content_type: &str,
+        metadata: &MediaMetadata,
+    ) -> Result<(), StorageError> {
+        let size = byte_stream.size_hint().1;
+        self.put(&self.key_layout().media_key(id), byte_stream, content_type, &metadata.to_object_metadata()).await?;
+        if let Some(size) = size {
+            metrics::counter!(MET_BYTES_STORED).increment(size);
+        }
+        Ok(())
+    }
+
+    /// Stores the preview under its content hash, see [Self::save_alias]
+    async fn save_content(
+        &self,
+        content_hash: &str,
+        byte_stream: ByteStream,
+        content_type: &str,
+        metadata: &MediaMetadata,
+    ) -> Result<(), StorageError> {
+        let size = byte_stream.size_hint().1;
+        self.put(&self.key_layout().content_key(content_hash), byte_stream, content_type, &metadata.to_object_metadata()).await?;
+        if let Some(size) = size {
+            metrics::counter!(MET_BYTES_STORED).increment(size);
+        }
+        Ok(())
+    }
+
+    /// Makes the preview stored under the content hash available for the given asset
+    async fn save_alias(&self, id: &str, content_hash: &str, metadata: &MediaMetadata) -> Result<(), StorageError> {
+        let target = self.key_layout().content_key(content_hash);
+        let mut object_metadata = metadata.to_object_metadata();
+        object_metadata.insert(metadata::ALIAS_TARGET.to_string(), target.clone());
+        self.put(&self.key_layout().media_key(id), ByteStream::from(target.into_bytes()), ALIAS_MIME, &object_metadata).await
+    }
+
+    async fn presigned_media_url(&self, id: &str, ttl: Duration) -> Result<Option<String>, StorageError> {
+        self.presigned_url(&self.media_key(id).await?, ttl).await
+    }
+}
+
+/// Creates the storage backend selected in the config,
+/// bootstrapping and checking it first if it's configured so
+pub async fn create_media_store(cfg: &Settings, overflow: QueueOverflow) -> anyhow::Result<Arc<dyn MediaStore + Send + Sync>> {
+    let key_layout = KeyLayout::new(&cfg.media_store);
+    let media_store: Arc<dyn MediaStore + Send + Sync> = match cfg.media_store.backend {
+        MediaStoreBackend::S3 => create_replicated_s3_store(cfg, key_layout, overflow).await?,
+        MediaStoreBackend::Fs => Arc::new(FsMediaStore::new(&cfg.media_store.fs_root_dir, key_layout).await?),
+        MediaStoreBackend::Memory => Arc::new(MemoryMediaStore::new(key_layout)),
+    };
+    if cfg.media_store.startup_self_check {
+        self_check(media_store.as_ref()).await.context("Media store self-check has failed")?;
+    }
+    Ok(media_store)
+}
+
+/// `obj_storage` store, wrapped into [ReplicatedMediaStore] if there are `obj_storage_replicas`.
+/// Replicas are checked at startup on their own, since the writes are replicated in background.
+async fn create_replicated_s3_store(
+    cfg: &Settings,
+    key_layout: KeyLayout,
+    overflow: QueueOverflow,
+) -> anyhow::Result<Arc<dyn MediaStore + Send + Sync>> {
+    let primary = Arc::new(create_s3_store(&cfg.obj_storage, key_layout.clone()).await?);
+    if cfg.obj_storage_replicas.is_empty() {
+        return Ok(primary);
+    }
+    let mut replicas = Vec::new();
+    for replica_cfg in &cfg.obj_storage_replicas {
+        let name = replica_cfg.storage.bucket_for_media.clone();
+        let store = create_s3_store(&replica_cfg.storage, key_layout.clone()).await?;
+        if cfg.media_store.startup_self_check {
+            match replica_cfg.role {
+                ReplicaRole::Secondary => self_check(&store).await,
+                ReplicaRole::ReadFallback => store.check().await.map_err(Into::into),
+            }.with_context(|| format!("Self-check of replica {name} has failed"))?;
+        }
+        replicas.push(Replica { name, role: replica_cfg.role, store: Arc::new(store) });
+    }
+    Ok(Arc::new(ReplicatedMediaStore::new(primary, replicas, overflow)))
+}
+
+/// Creates the S3 store, creating the bucket if `create_bucket_if_missing` is set
+async fn create_s3_store(obj_storage: &ObjStorage, key_layout: KeyLayout) -> anyhow::Result<S3MediaStore> {
+    let store = S3MediaStore::new(obj_storage, key_layout).await;
+    if obj_storage.create_bucket_if_missing {
+        store.create_bucket_if_missing().await
+            .with_context(|| format!("Cannot create bucket {}", obj_storage.bucket_for_media))?;
+    }
+    Ok(store)
+}
+
+fn record_operation<T, E>(operation: &'static str, start: Instant, result: &Result<T, E>) {
+    metrics::histogram!(MET_STORAGE_OPERATION_DURATION, CAT_OPERATION => operation, CAT_OUTCOME => outcome(result))
+        .record(start.elapsed().as_secs_f64());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_aliases_are_followed() {
+        let store = MemoryMediaStore::default();
+        store.save_content("hash1", ByteStream::from_static(b"image"), "image/webp", &test_metadata("https://a/1.png", 1)).await.unwrap();
+        store.save_alias("asset1", "hash1", &test_metadata("https://a/1.png", 1)).await.unwrap();
+        store.save_alias("asset2", "hash1", &test_metadata("https://b/2.png", 1)).await.unwrap();
+
+        let stored = store.get_media("asset2").await.unwrap();
+        assert_eq!(stored.mime, "image/webp");
+        assert_eq!(stored.bytes.collect().await.unwrap().into_bytes().as_ref(), b"image");
+
+        let info = store.head_media("asset2").await.unwrap();
+        assert_eq!(info.key, "content/hash1");
+        assert_eq!(info.size, Some(5));
+        assert_eq!(MediaMetadata::from_object_metadata(&info.metadata), Some(test_metadata("https://b/2.png", 1)));
+
+        assert!(matches!(store.get_media("asset3").await, Err(StorageError::NotFound)));
+        store.save_alias("asset3", "missing", &test_metadata("https://c/3.png", 1)).await.unwrap();
+        assert!(matches!(store.get_media("asset3").await, Err(StorageError::NotFound)));
+    }
+}
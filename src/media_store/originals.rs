@@ -0,0 +1,117 @@
+use std::sync::Arc;
+
+use anyhow::Context;
+use bytes::Bytes;
+
+use crate::{
+    app_metrics::{CAT_OUTCOME, MET_BYTES_STORED, MET_ORIGINALS_ARCHIVED},
+    configs::{MediaStoreBackend, OriginalsCfg, Settings},
+    media_type::{AssetClass, Mime},
+};
+use super::{create_s3_store, metadata, self_check, MediaStore, ObjectInfo, StorageError, StoredData};
+
+/// Keeps the downloaded originals with their real content type,
+/// either next to the previews (under a separate key prefix) or in a separate bucket
+pub struct OriginalsArchive {
+    store: Arc<dyn MediaStore + Send + Sync>,
+    cfg: OriginalsCfg,
+}
+
+impl OriginalsArchive {
+    pub fn new(store: Arc<dyn MediaStore + Send + Sync>, cfg: OriginalsCfg) -> OriginalsArchive {
+        OriginalsArchive { store, cfg }
+    }
+
+    /// Object key, under which the original of the given asset is stored
+    pub fn key_for(&self, asset_id: &str) -> String {
+        format!("{}/{}", self.cfg.key_prefix, asset_id)
+    }
+
+    fn max_size(&self, class: AssetClass) -> u64 {
+        match class {
+            AssetClass::Image => self.cfg.image_max_size_bytes,
+            AssetClass::Video => self.cfg.video_max_size_bytes,
+            AssetClass::Other => self.cfg.other_max_size_bytes,
+        }
+    }
+
+    /// Stores the original, unless it exceeds the limit of its class.
+    /// Failures are only logged, since the preview is more important than the archive.
+    pub async fn archive(&self, id: &str, url: &str, bytes: &Bytes, mime: &Mime) {
+        let size = bytes.len() as u64;
+        if size > self.max_size(mime.class) {
+            metrics::counter!(MET_ORIGINALS_ARCHIVED, CAT_OUTCOME => "too_large").increment(1);
+            return;
+        }
+        let result = self.store.put(&self.key_for(id), bytes.clone().into(), mime.str(), &metadata::original_object_metadata(url)).await;
+        match result {
+            Ok(()) => {
+                metrics::counter!(MET_ORIGINALS_ARCHIVED, CAT_OUTCOME => "archived").increment(1);
+                metrics::counter!(MET_BYTES_STORED).increment(size);
+            },
+            Err(err) => {
+                metrics::counter!(MET_ORIGINALS_ARCHIVED, CAT_OUTCOME => "failed").increment(1);
+                tracing::warn!(url_hash = %id, error = %err, "Cannot archive the original");
+            },
+        }
+    }
+
+    pub async fn get(&self, id: &str) -> Result<StoredData, StorageError> {
+        self.store.get(&self.key_for(id)).await
+    }
+
+    pub async fn head(&self, id: &str) -> Result<ObjectInfo, StorageError> {
+        self.store.head(&self.key_for(id)).await
+    }
+
+    pub async fn delete(&self, id: &str) -> Result<(), StorageError> {
+        self.store.delete(&self.key_for(id)).await
+    }
+}
+
+/// Creates the archive if it's enabled, sharing the media store unless a separate bucket is configured.
+/// The separate bucket is bootstrapped and checked the same way as the media store.
+pub async fn create_originals_archive(
+    cfg: &Settings,
+    media_store: &Arc<dyn MediaStore + Send + Sync>,
+) -> anyhow::Result<Option<Arc<OriginalsArchive>>> {
+    if !cfg.originals.enabled {
+        return Ok(None);
+    }
+    let store: Arc<dyn MediaStore + Send + Sync> = match (&cfg.originals.bucket, cfg.media_store.backend) {
+        (Some(bucket), MediaStoreBackend::S3) => {
+            let mut obj_storage = cfg.obj_storage.clone();
+            obj_storage.bucket_for_media = bucket.clone();
+            let store = Arc::new(create_s3_store(&obj_storage, media_store.key_layout().clone()).await?);
+            if cfg.media_store.startup_self_check {
+                self_check(store.as_ref()).await.context("Originals bucket self-check has failed")?;
+            }
+            store
+        },
+        _ => media_store.clone(),
+    };
+    Ok(Some(Arc::new(OriginalsArchive::new(store, cfg.originals.clone()))))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::media_store::MemoryMediaStore;
+
+    #[tokio::test]
+    async fn test_originals_are_limited_by_class() {
+        let store = Arc::new(MemoryMediaStore::default());
+        let cfg = OriginalsCfg { enabled: true, image_max_size_bytes: 4, video_max_size_bytes: 0, ..Default::default() };
+        let archive = OriginalsArchive::new(store.clone(), cfg);
+
+        archive.archive("img", "https://a/1.png", &Bytes::from_static(b"png"), &Mime::from_mime_str("image/png")).await;
+        archive.archive("big", "https://a/2.png", &Bytes::from_static(b"large"), &Mime::from_mime_str("image/png")).await;
+        archive.archive("video", "https://a/3.mp4", &Bytes::from_static(b"mp4"), &Mime::from_mime_str("video/mp4")).await;
+
+        let stored = archive.get("img").await.unwrap();
+        assert_eq!(stored.mime, "image/png");
+        assert_eq!(store.head("originals/img").await.unwrap().metadata["original-url"], "https://a/1.png");
+        assert!(matches!(archive.get("big").await, Err(StorageError::NotFound)));
+        assert!(matches!(archive.get("video").await, Err(StorageError::NotFound)));
+    }
+}
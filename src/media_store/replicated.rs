@@ -0,0 +1,365 @@
+use std::{hash::{DefaultHasher, Hash, Hasher}, sync::{atomic::{AtomicU64, Ordering}, Arc}, time::Duration};
+
+use async_trait::async_trait;
+use aws_sdk_s3::primitives::ByteStream;
+use futures::future::BoxFuture;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::{
+    app_metrics::{outcome, CAT_OPERATION, CAT_OUTCOME, CAT_REPLICA, MET_READ_FALLBACKS, MET_REPLICATION},
+    configs::ReplicaRole,
+};
+use super::{KeyLayout, KeysPage, MediaStore, ObjectInfo, ObjectMetadata, StorageError, StoredData};
+
+/// Operations waiting to be replicated to a secondary storage, see [QueueOverflow] for what happens to further ones
+const REPLICATION_QUEUE_SIZE: usize = 10_000;
+/// Operations being replicated to a secondary storage at the same time, each by its own queue
+const REPLICATION_CONCURRENCY: usize = 4;
+
+pub struct Replica {
+    /// Name of the replica in logs and metrics
+    pub name: String,
+    pub role: ReplicaRole,
+    pub store: Arc<dyn MediaStore + Send + Sync>,
+}
+
+/// What writes do, when a secondary storage lags behind and its replication queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueueOverflow {
+    /// Operations are dropped, so that a slow replica never slows the service down
+    Drop,
+    /// Writes wait for a free slot in the queue, so that one-shot commands writing a lot lose nothing
+    Wait,
+}
+
+#[derive(Clone)]
+enum ReplicationOp {
+    Put(String),
+    Delete(String),
+}
+
+impl ReplicationOp {
+    fn key(&self) -> &str {
+        match self {
+            ReplicationOp::Put(key) | ReplicationOp::Delete(key) => key,
+        }
+    }
+
+    /// Label value used in metrics
+    fn name(&self) -> &'static str {
+        match self {
+            ReplicationOp::Put(_) => "put",
+            ReplicationOp::Delete(_) => "delete",
+        }
+    }
+}
+
+enum QueueItem {
+    Op(ReplicationOp),
+    /// Answered once the operations queued before are replicated, with the number of the failed ones
+    Flush(oneshot::Sender<u64>),
+}
+
+/// Writes to the primary storage, and replicates the writes to the secondary ones in background,
+/// so that a slow or unavailable replica does not slow down or fail the processing.
+/// Reads go to the primary storage and fall back to the replicas if it fails, unless the object is just missing.
+///
+/// Replication failures are only logged and counted, they are not retried,
+/// but they are reported by [MediaStore::flush], which waits for the replication to complete.
+pub struct ReplicatedMediaStore {
+    primary: Arc<dyn MediaStore + Send + Sync>,
+    replicas: Vec<Replica>,
+    /// Replication queues of the secondary replicas, with the replica names.
+    /// Operations on a key always go to the same queue, and each queue is replicated in order,
+    /// so that e.g. a delete and the following put of the same key are not reordered.
+    queues: Vec<(String, Vec<mpsc::Sender<QueueItem>>)>,
+    overflow: QueueOverflow,
+    /// Operations dropped since the last flush
+    dropped: AtomicU64,
+}
+
+impl ReplicatedMediaStore {
+    /// Starts the replication workers, so it has to be called within the Tokio runtime
+    pub fn new(
+        primary: Arc<dyn MediaStore + Send + Sync>,
+        replicas: Vec<Replica>,
+        overflow: QueueOverflow,
+    ) -> ReplicatedMediaStore {
+        let queues = replicas.iter()
+            .filter(|replica| replica.role == ReplicaRole::Secondary)
+            .map(|replica| {
+                let senders = (0 .. REPLICATION_CONCURRENCY).map(|_| {
+                    let (sender, receiver) = mpsc::channel(REPLICATION_QUEUE_SIZE / REPLICATION_CONCURRENCY);
+                    tokio::spawn(run_replication(primary.clone(), replica.name.clone(), replica.store.clone(), receiver));
+                    sender
+                }).collect();
+                (replica.name.clone(), senders)
+            })
+            .collect();
+        ReplicatedMediaStore { primary, replicas, queues, overflow, dropped: AtomicU64::new(0) }
+    }
+
+    async fn replicate(&self, op: ReplicationOp) {
+        let mut hasher = DefaultHasher::new();
+        op.key().hash(&mut hasher);
+        let queue_index = hasher.finish() as usize % REPLICATION_CONCURRENCY;
+        for (name, queues) in &self.queues {
+            let item = QueueItem::Op(op.clone());
+            let queued = match self.overflow {
+                QueueOverflow::Drop => queues[queue_index].try_send(item).is_ok(),
+                QueueOverflow::Wait => queues[queue_index].send(item).await.is_ok(),
+            };
+            if !queued {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+                metrics::counter!(MET_REPLICATION, CAT_REPLICA => name.clone(), CAT_OPERATION => op.name(), CAT_OUTCOME => "dropped").increment(1);
+                tracing::warn!(replica = name, key = op.key(), "Replication queue is full, the operation is dropped");
+            }
+        }
+    }
+
+    async fn read_with_fallback<'a, T: Send>(
+        &'a self,
+        operation: &'static str,
+        key: &'a str,
+        read: impl Fn(&'a (dyn MediaStore + Send + Sync)) -> BoxFuture<'a, Result<T, StorageError>> + Send + Sync,
+    ) -> Result<T, StorageError> {
+        let result = read(self.primary.as_ref()).await;
+        let primary_error = match &result {
+            Ok(_) | Err(StorageError::NotFound) => return result,
+            Err(err) => err.to_string(),
+        };
+        for replica in &self.replicas {
+            match read(replica.store.as_ref()).await {
+                Ok(value) => {
+                    metrics::counter!(MET_READ_FALLBACKS, CAT_REPLICA => replica.name.clone(), CAT_OPERATION => operation, CAT_OUTCOME => "success").increment(1);
+                    tracing::warn!(key, replica = replica.name, primary_error, "Primary storage has failed, object is read from the replica");
+                    return Ok(value);
+                },
+                Err(err) => {
+                    metrics::counter!(MET_READ_FALLBACKS, CAT_REPLICA => replica.name.clone(), CAT_OPERATION => operation, CAT_OUTCOME => "error").increment(1);
+                    tracing::warn!(key, replica = replica.name, error = %err, "Cannot read object from the replica");
+                },
+            }
+        }
+        result
+    }
+}
+
+#[async_trait]
+impl MediaStore for ReplicatedMediaStore {
+    fn key_layout(&self) -> &KeyLayout {
+        self.primary.key_layout()
+    }
+
+    async fn get(&self, key: &str) -> Result<StoredData, StorageError> {
+        self.read_with_fallback("get", key, |store| store.get(key)).await
+    }
+
+    async fn head(&self, key: &str) -> Result<ObjectInfo, StorageError> {
+        self.read_with_fallback("head", key, |store| store.head(key)).await
+    }
+
+    async fn put(&self, key: &str, byte_stream: ByteStream, content_type: &str, metadata: &ObjectMetadata) -> Result<(), StorageError> {
+        self.primary.put(key, byte_stream, content_type, metadata).await?;
+        self.replicate(ReplicationOp::Put(key.to_string())).await;
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        self.primary.delete(key).await?;
+        self.replicate(ReplicationOp::Delete(key.to_string())).await;
+        Ok(())
+    }
+
+    async fn list(&self, prefix: &str, continuation: Option<String>) -> Result<KeysPage, StorageError> {
+        self.primary.list(prefix, continuation).await
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        self.primary.copy(from, to).await?;
+        self.replicate(ReplicationOp::Put(to.to_string())).await;
+        Ok(())
+    }
+
+    /// Only the primary storage is checked, the service keeps working while replicas are unavailable
+    async fn check(&self) -> Result<(), StorageError> {
+        self.primary.check().await
+    }
+
+    async fn presigned_url(&self, key: &str, ttl: Duration) -> Result<Option<String>, StorageError> {
+        self.primary.presigned_url(key, ttl).await
+    }
+
+    /// Fails if any operation since the previous flush has been dropped or has failed
+    async fn flush(&self) -> Result<(), StorageError> {
+        let mut answers = Vec::new();
+        for (_, queues) in &self.queues {
+            for queue in queues {
+                let (answer, answered) = oneshot::channel();
+                // Waits for a free slot regardless of the overflow mode, nothing else has to be replicated before
+                queue.send(QueueItem::Flush(answer)).await
+                    .map_err(|_| StorageError::Other("Replication has stopped".to_string()))?;
+                answers.push(answered);
+            }
+        }
+        let mut failed = self.dropped.swap(0, Ordering::Relaxed);
+        for answered in answers {
+            failed += answered.await.map_err(|_| StorageError::Other("Replication has stopped".to_string()))?;
+        }
+        if failed > 0 {
+            return Err(StorageError::Unavailable(format!("{failed} operations have not been replicated")));
+        }
+        Ok(())
+    }
+}
+
+async fn run_replication(
+    primary: Arc<dyn MediaStore + Send + Sync>,
+    name: String,
+    replica: Arc<dyn MediaStore + Send + Sync>,
+    mut queue: mpsc::Receiver<QueueItem>,
+) {
+    let mut failed = 0;
+    while let Some(item) = queue.recv().await {
+        let op = match item {
+            QueueItem::Op(op) => op,
+            QueueItem::Flush(answer) => {
+                let _ = answer.send(std::mem::take(&mut failed));
+                continue;
+            },
+        };
+        let result = match &op {
+            ReplicationOp::Put(key) => copy_object(primary.as_ref(), replica.as_ref(), key).await,
+            ReplicationOp::Delete(key) => replica.delete(key).await,
+        };
+        if let Err(err) = &result {
+            failed += 1;
+            tracing::warn!(replica = name, key = op.key(), operation = op.name(), error = %err, "Cannot replicate object");
+        }
+        metrics::counter!(MET_REPLICATION, CAT_REPLICA => name.clone(), CAT_OPERATION => op.name(), CAT_OUTCOME => outcome(&result)).increment(1);
+    }
+}
+
+/// Copies the object as it is in the primary storage at the moment, so a lagging replication
+/// never overwrites a newer version. An object deleted meanwhile is skipped, its deletion follows.
+async fn copy_object(
+    from: &(dyn MediaStore + Send + Sync),
+    to: &(dyn MediaStore + Send + Sync),
+    key: &str,
+) -> Result<(), StorageError> {
+    let stored = match from.get(key).await {
+        Ok(stored) => stored,
+        Err(StorageError::NotFound) => return Ok(()),
+        Err(err) => return Err(err),
+    };
+    to.put(key, stored.bytes, &stored.mime, &stored.metadata).await
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::media_store::{MemoryMediaStore, UnavailableStore};
+
+    fn replica(role: ReplicaRole, store: Arc<dyn MediaStore + Send + Sync>) -> Replica {
+        Replica { name: "replica".to_string(), role, store }
+    }
+
+    /// Replication is asynchronous, so the replica is polled for a while
+    async fn wait_for(store: &MemoryMediaStore, key: &str, exists: bool) {
+        for _ in 0 .. 100 {
+            if store.head(key).await.is_ok() == exists {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("{key} has not been replicated");
+    }
+
+    #[tokio::test]
+    async fn test_writes_are_replicated() {
+        let primary = Arc::new(MemoryMediaStore::default());
+        let secondary = Arc::new(MemoryMediaStore::default());
+        let fallback = Arc::new(MemoryMediaStore::default());
+        let store = ReplicatedMediaStore::new(primary.clone(), vec![
+            replica(ReplicaRole::Secondary, secondary.clone()),
+            replica(ReplicaRole::ReadFallback, fallback.clone()),
+        ], QueueOverflow::Drop);
+
+        let metadata = ObjectMetadata::from([("original-url".to_string(), "https://a/1.png".to_string())]);
+        store.put("media/1", ByteStream::from_static(b"one"), "image/webp", &metadata).await.unwrap();
+        store.copy("media/1", "media/2").await.unwrap();
+        wait_for(&secondary, "media/2", true).await;
+        assert_eq!(secondary.get("media/1").await.unwrap().bytes.collect().await.unwrap().into_bytes().as_ref(), b"one");
+        assert_eq!(secondary.head("media/1").await.unwrap().mime, "image/webp");
+        assert_eq!(secondary.head("media/2").await.unwrap().metadata, metadata);
+
+        store.delete("media/1").await.unwrap();
+        wait_for(&secondary, "media/1", false).await;
+        assert!(fallback.list("", None).await.unwrap().keys.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_operations_on_a_key_are_not_reordered() {
+        let primary = Arc::new(MemoryMediaStore::default());
+        let secondary = Arc::new(MemoryMediaStore::default());
+        let store = ReplicatedMediaStore::new(primary, vec![replica(ReplicaRole::Secondary, secondary.clone())], QueueOverflow::Drop);
+
+        for _ in 0 .. 20 {
+            store.put("media/1", ByteStream::from_static(b"one"), "image/webp", &ObjectMetadata::new()).await.unwrap();
+            store.delete("media/1").await.unwrap();
+        }
+        store.put("media/1", ByteStream::from_static(b"one"), "image/webp", &ObjectMetadata::new()).await.unwrap();
+        wait_for(&secondary, "media/1", true).await;
+        // Object may appear in between, give the rest of the operations time to be replicated
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(secondary.head("media/1").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_flush_waits_for_replication() {
+        let primary = Arc::new(MemoryMediaStore::default());
+        let secondary = Arc::new(MemoryMediaStore::default());
+        let store = ReplicatedMediaStore::new(primary, vec![replica(ReplicaRole::Secondary, secondary.clone())], QueueOverflow::Wait);
+
+        // More operations than the queues can hold, none of them is dropped
+        let count = REPLICATION_QUEUE_SIZE + 100;
+        for i in 0 .. count {
+            store.put(&format!("media/{i}"), ByteStream::from_static(b"one"), "image/webp", &ObjectMetadata::new()).await.unwrap();
+        }
+        store.flush().await.unwrap();
+        assert_eq!(secondary.list("media/", None).await.unwrap().keys.len(), count);
+    }
+
+    #[tokio::test]
+    async fn test_flush_reports_failures() {
+        let store = ReplicatedMediaStore::new(Arc::new(MemoryMediaStore::default()), vec![
+            replica(ReplicaRole::Secondary, Arc::new(UnavailableStore::default())),
+        ], QueueOverflow::Wait);
+
+        store.put("media/1", ByteStream::from_static(b"one"), "image/webp", &ObjectMetadata::new()).await.unwrap();
+        assert!(matches!(store.flush().await, Err(StorageError::Unavailable(_))));
+        // Each failure is reported once
+        store.flush().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_reads_fall_back_to_replicas() {
+        let fallback = Arc::new(MemoryMediaStore::default());
+        fallback.put("media/1", ByteStream::from_static(b"one"), "image/webp", &ObjectMetadata::new()).await.unwrap();
+        let store = ReplicatedMediaStore::new(Arc::new(UnavailableStore::default()), vec![
+            replica(ReplicaRole::ReadFallback, fallback),
+        ], QueueOverflow::Drop);
+
+        assert_eq!(store.get_media("1").await.unwrap().bytes.collect().await.unwrap().into_bytes().as_ref(), b"one");
+        assert_eq!(store.head_media("1").await.unwrap().key, "media/1");
+        assert!(matches!(store.get("media/2").await, Err(StorageError::Unavailable(_))));
+        assert!(store.put("media/2", ByteStream::from_static(b"two"), "image/webp", &ObjectMetadata::new()).await.is_err());
+
+        // Missing objects are not looked up in the replicas
+        let primary = Arc::new(MemoryMediaStore::default());
+        let secondary = Arc::new(MemoryMediaStore::default());
+        secondary.put("media/1", ByteStream::from_static(b"one"), "image/webp", &ObjectMetadata::new()).await.unwrap();
+        let store = ReplicatedMediaStore::new(primary, vec![replica(ReplicaRole::Secondary, secondary)], QueueOverflow::Drop);
+        assert!(matches!(store.get("media/1").await, Err(StorageError::NotFound)));
+    }
+}
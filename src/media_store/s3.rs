@@ -0,0 +1,270 @@
+use async_trait::async_trait;
+use aws_config::Region;
+use aws_sdk_s3::{
+    config::http::HttpResponse,
+    error::{DisplayErrorContext, ProvideErrorMetadata, SdkError},
+    presigning::PresigningConfig,
+    primitives::ByteStream,
+    types::{BucketLocationConstraint, CreateBucketConfiguration},
+};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::time::Duration;
+use tokio::{io::AsyncReadExt, time::Instant};
+
+use crate::configs::{MultipartUploadCfg, ObjStorage};
+use super::{
+    record_operation, s3_credentials, s3_multipart::MultipartUpload, KeyLayout, KeysPage, MediaStore, ObjectInfo,
+    ObjectMetadata, StorageError, StoredData,
+};
+
+/// Characters that have to be encoded in the `x-amz-copy-source` header
+const COPY_SOURCE_ENCODE: &AsciiSet = &NON_ALPHANUMERIC.remove(b'/').remove(b'-').remove(b'_').remove(b'.').remove(b'~');
+
+/// Wrapper for S3 client that stores asset previews in a bucket
+pub struct S3MediaStore {
+    s3_client: aws_sdk_s3::Client,
+    media_bucket: String,
+    multipart: MultipartUploadCfg,
+    key_layout: KeyLayout,
+}
+
+impl S3MediaStore {
+    pub async fn new(cfg: &ObjStorage, key_layout: KeyLayout) -> S3MediaStore {
+        let media_bucket = cfg.bucket_for_media.clone();
+
+        let mut config_loader = aws_config::from_env();
+        if let Some(reg) = &cfg.region {
+            config_loader = config_loader.region(Region::new(reg.clone()));
+        }
+        if let Some(endpoint) = &cfg.endpoint {
+            config_loader = config_loader.endpoint_url(endpoint.clone());
+        }
+        config_loader = config_loader.credentials_provider(s3_credentials::credentials_provider(cfg).await);
+
+        let sdk_config = config_loader.load().await;
+
+        //let config = aws_config::load_from_env().await;
+        let s3_client = aws_sdk_s3::Client::new(&sdk_config);
+
+        S3MediaStore {
+            s3_client,
+            media_bucket,
+            multipart: cfg.multipart.clone(),
+            key_layout,
+        }
+    }
+
+    /// Creates the media bucket unless it exists, meant for MinIO and development setups.
+    /// Returns whether the bucket has been created.
+    pub async fn create_bucket_if_missing(&self) -> Result<bool, StorageError> {
+        match self.check().await {
+            Err(StorageError::NotFound) => (),
+            result => return result.map(|()| false),
+        }
+        let mut request = self.s3_client.create_bucket().bucket(&self.media_bucket);
+        // us-east-1 is the default location, S3 rejects it as an explicit constraint
+        if let Some(region) = self.s3_client.config().region().map(|r| r.as_ref()).filter(|r| *r != "us-east-1") {
+            let location = CreateBucketConfiguration::builder()
+                .location_constraint(BucketLocationConstraint::from(region))
+                .build();
+            request = request.create_bucket_configuration(location);
+        }
+        let start = Instant::now();
+        let resp = request.send().await;
+        record_operation("create_bucket", start, &resp);
+        match resp {
+            Ok(_) => {
+                tracing::info!(bucket = self.media_bucket, "Bucket has been created");
+                Ok(true)
+            },
+            // Another instance has just created it
+            Err(err) if err.as_service_error().is_some_and(|e| e.is_bucket_already_owned_by_you()) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+#[async_trait]
+impl MediaStore for S3MediaStore {
+    fn key_layout(&self) -> &KeyLayout {
+        &self.key_layout
+    }
+
+    #[tracing::instrument(name = "storage_get", skip(self))]
+    async fn get(&self, key: &str) -> Result<StoredData, StorageError> {
+        let start = Instant::now();
+        let resp = self.s3_client.get_object()
+            .bucket(&self.media_bucket)
+            .key(key)
+            .send().await;
+        record_operation("get_object", start, &resp);
+        let resp = resp?;
+
+        let mime = resp.content_type.unwrap_or("application/octet-stream".to_string());
+        let size = resp.content_length.and_then(|l| u64::try_from(l).ok());
+        let metadata = resp.metadata.unwrap_or_default();
+        let bytes = resp.body;
+
+        Ok(StoredData { bytes, mime, size, metadata })
+    }
+
+    #[tracing::instrument(name = "storage_head", skip(self))]
+    async fn head(&self, key: &str) -> Result<ObjectInfo, StorageError> {
+        let start = Instant::now();
+        let resp = self.s3_client.head_object()
+            .bucket(&self.media_bucket)
+            .key(key)
+            .send().await;
+        record_operation("head_object", start, &resp);
+        let resp = resp?;
+
+        Ok(ObjectInfo {
+            key: key.to_string(),
+            mime: resp.content_type.unwrap_or("application/octet-stream".to_string()),
+            size: resp.content_length.and_then(|l| u64::try_from(l).ok()),
+            metadata: resp.metadata.unwrap_or_default(),
+        })
+    }
+
+    #[tracing::instrument(name = "storage_delete", skip(self))]
+    async fn delete(&self, key: &str) -> Result<(), StorageError> {
+        let start = Instant::now();
+        let resp = self.s3_client.delete_object()
+            .bucket(&self.media_bucket)
+            .key(key)
+            .send().await;
+        record_operation("delete_object", start, &resp);
+        resp?;
+        Ok(())
+    }
+
+    async fn list(&self, prefix: &str, continuation: Option<String>) -> Result<KeysPage, StorageError> {
+        let start = Instant::now();
+        let resp = self.s3_client.list_objects_v2()
+            .bucket(&self.media_bucket)
+            .prefix(prefix)
+            .set_continuation_token(continuation)
+            .send().await;
+        record_operation("list_objects", start, &resp);
+        let resp = resp?;
+
+        let keys = resp.contents.unwrap_or_default().into_iter()
+            .filter_map(|object| object.key)
+            .collect();
+        let next = resp.is_truncated.unwrap_or_default().then_some(resp.next_continuation_token).flatten();
+        Ok(KeysPage { keys, next })
+    }
+
+    /// Server-side copy, content type and metadata are copied as well
+    #[tracing::instrument(name = "storage_copy", skip(self))]
+    async fn copy(&self, from: &str, to: &str) -> Result<(), StorageError> {
+        let start = Instant::now();
+        let resp = self.s3_client.copy_object()
+            .bucket(&self.media_bucket)
+            .copy_source(format!("{}/{}", self.media_bucket, utf8_percent_encode(from, COPY_SOURCE_ENCODE)))
+            .key(to)
+            .send().await;
+        record_operation("copy_object", start, &resp);
+        resp?;
+        Ok(())
+    }
+
+    /// Checks that the media bucket exists and is accessible with the configured credentials
+    async fn check(&self) -> Result<(), StorageError> {
+        let start = Instant::now();
+        let resp = self.s3_client.head_bucket()
+            .bucket(&self.media_bucket)
+            .send()
+            .await;
+        record_operation("head_bucket", start, &resp);
+        resp?;
+        Ok(())
+    }
+
+    async fn presigned_url(&self, key: &str, ttl: Duration) -> Result<Option<String>, StorageError> {
+        let presigning_cfg = PresigningConfig::expires_in(ttl)
+            .map_err(|e| StorageError::Other(e.to_string()))?;
+        let req = self.s3_client.get_object()
+            .bucket(&self.media_bucket)
+            .key(key)
+            .presigned(presigning_cfg)
+            .await?;
+        Ok(Some(req.uri().to_string()))
+    }
+
+    /// Objects up to the multipart threshold are uploaded with a single request,
+    /// bigger ones are streamed in parts, see [MultipartUpload]
+    #[tracing::instrument(name = "storage_put", skip(self, byte_stream))]
+    async fn put(&self, key: &str, byte_stream: ByteStream, content_type: &str, metadata: &ObjectMetadata) -> Result<(), StorageError> {
+        let mut content = byte_stream.into_async_read();
+        let mut head = Vec::new();
+        (&mut content).take(self.multipart.threshold_bytes).read_to_end(&mut head).await
+            .map_err(|e| StorageError::Other(format!("Cannot read the content: {e}")))?;
+        if head.len() as u64 >= self.multipart.threshold_bytes {
+            let upload = MultipartUpload { client: &self.s3_client, bucket: &self.media_bucket, key, cfg: &self.multipart };
+            return upload.run(content_type, metadata, head, content).await;
+        }
+
+        let start = Instant::now();
+        let resp = self.s3_client.put_object()
+            .bucket(&self.media_bucket)
+            .key(key)
+            .content_type(content_type)
+            .set_metadata(Some(metadata.clone()))
+            .body(ByteStream::from(head))
+            .send()
+            .await;
+        record_operation("put_object", start, &resp);
+        resp?;
+        Ok(())
+    }
+}
+
+impl<E: ProvideErrorMetadata + std::error::Error + 'static> From<SdkError<E, HttpResponse>> for StorageError {
+    fn from(value: SdkError<E, HttpResponse>) -> Self {
+        match &value {
+            SdkError::ServiceError(ctx) => match ctx.raw().status().as_u16() {
+                404 => StorageError::NotFound,
+                401 | 403 => StorageError::Forbidden,
+                429 | 500 ..= 599 => StorageError::Unavailable(DisplayErrorContext(&value).to_string()),
+                _ => match ctx.err().code() {
+                    Some("NoSuchKey") | Some("NotFound") => StorageError::NotFound,
+                    Some("AccessDenied") => StorageError::Forbidden,
+                    _ => StorageError::Other(DisplayErrorContext(&value).to_string()),
+                },
+            },
+            SdkError::TimeoutError(_) | SdkError::DispatchFailure(_) | SdkError::ResponseError(_) =>
+                StorageError::Unavailable(DisplayErrorContext(&value).to_string()),
+            _ => StorageError::Other(DisplayErrorContext(&value).to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use aws_sdk_s3::{
+        operation::get_object::GetObjectError,
+        primitives::SdkBody,
+        types::error::NoSuchKey,
+    };
+
+    fn service_error(status: u16, err: GetObjectError) -> StorageError {
+        let raw = HttpResponse::new(status.try_into().unwrap(), SdkBody::empty());
+        SdkError::service_error(err, raw).into()
+    }
+
+    #[test]
+    fn test_storage_error_from_sdk_error() {
+        let no_such_key = || GetObjectError::NoSuchKey(NoSuchKey::builder().build());
+        let unhandled = || GetObjectError::unhandled("boom");
+
+        assert!(matches!(service_error(404, no_such_key()), StorageError::NotFound));
+        assert!(matches!(service_error(403, unhandled()), StorageError::Forbidden));
+        assert!(matches!(service_error(503, unhandled()), StorageError::Unavailable(_)));
+        assert!(matches!(service_error(400, unhandled()), StorageError::Other(_)));
+
+        let timeout: StorageError = SdkError::<GetObjectError, HttpResponse>::timeout_error("slow").into();
+        assert!(matches!(timeout, StorageError::Unavailable(_)));
+    }
+}
@@ -0,0 +1,176 @@
+//! Credentials for the object storage, selected by the `obj_storage.credentials` setting.
+use std::time::{Duration, SystemTime};
+
+use aws_config::{
+    default_provider::credentials::DefaultCredentialsChain,
+    imds::credentials::ImdsCredentialsProvider,
+    profile::ProfileFileCredentialsProvider,
+    provider_config::ProviderConfig,
+    web_identity_token::{StaticConfiguration, WebIdentityTokenCredentialsProvider},
+    Region,
+};
+use aws_credential_types::provider::{error::CredentialsError, future, ProvideCredentials};
+use aws_sdk_s3::config::{Credentials, SharedCredentialsProvider};
+
+use crate::configs::{CredentialsSource, ObjStorage};
+
+const WEB_IDENTITY_SESSION_NAME: &str = "media-files-store";
+
+/// Creates credentials provider for the configured source.
+/// Providers of temporary credentials (web identity, IMDS, etc.) are refreshed by the SDK
+/// before the credentials expire.
+pub async fn credentials_provider(cfg: &ObjStorage) -> SharedCredentialsProvider {
+    // Region is needed by STS, if not configured it's taken from the environment the same way the SDK does
+    let provider_config = match &cfg.region {
+        Some(region) => ProviderConfig::default().with_region(Some(Region::new(region.clone()))),
+        None => ProviderConfig::default().load_default_region().await,
+    };
+
+    match cfg.credentials_source() {
+        CredentialsSource::Static => {
+            if cfg.access_key_id_file.is_some() || cfg.secret_access_key_file.is_some() || cfg.session_token_file.is_some() {
+                SharedCredentialsProvider::new(FileCredentialsProvider::new(cfg))
+            } else {
+                SharedCredentialsProvider::new(Credentials::new(
+                    cfg.access_key_id.clone().unwrap_or_default(),
+                    cfg.secret_access_key.clone().unwrap_or_default(),
+                    cfg.session_token.clone(),
+                    None,
+                    "settings",
+                ))
+            }
+        },
+        CredentialsSource::DefaultChain => {
+            let mut chain = DefaultCredentialsChain::builder().configure(provider_config);
+            if let Some(profile_name) = &cfg.profile_name {
+                chain = chain.profile_name(profile_name);
+            }
+            SharedCredentialsProvider::new(chain.build().await)
+        },
+        CredentialsSource::Profile => {
+            let mut builder = ProfileFileCredentialsProvider::builder().configure(&provider_config);
+            if let Some(profile_name) = &cfg.profile_name {
+                builder = builder.profile_name(profile_name);
+            }
+            SharedCredentialsProvider::new(builder.build())
+        },
+        CredentialsSource::WebIdentity => {
+            let mut builder = WebIdentityTokenCredentialsProvider::builder().configure(&provider_config);
+            if let (Some(role_arn), Some(token_file)) = (&cfg.role_arn, &cfg.web_identity_token_file) {
+                builder = builder.static_configuration(StaticConfiguration {
+                    web_identity_token_file: token_file.into(),
+                    role_arn: role_arn.clone(),
+                    session_name: WEB_IDENTITY_SESSION_NAME.to_string(),
+                });
+            }
+            SharedCredentialsProvider::new(builder.build())
+        },
+        CredentialsSource::Imds => {
+            SharedCredentialsProvider::new(ImdsCredentialsProvider::builder().configure(&provider_config).build())
+        },
+    }
+}
+
+/// Static credentials that are read from files, values from the config are used for the keys that have no file.
+/// Returned credentials "expire" after the refresh interval, so that the SDK asks for them again,
+/// and rotated secrets are picked up.
+#[derive(Debug)]
+pub struct FileCredentialsProvider {
+    access_key_id: CredentialPart,
+    secret_access_key: CredentialPart,
+    session_token: Option<CredentialPart>,
+    refresh_interval: Duration,
+}
+
+#[derive(Debug)]
+enum CredentialPart {
+    Value(String),
+    File(String),
+}
+
+impl CredentialPart {
+    fn from_cfg(value: &Option<String>, file: &Option<String>) -> Option<CredentialPart> {
+        match (file, value) {
+            (Some(file), _) => Some(CredentialPart::File(file.clone())),
+            (None, Some(value)) => Some(CredentialPart::Value(value.clone())),
+            (None, None) => None,
+        }
+    }
+
+    async fn read(&self) -> Result<String, CredentialsError> {
+        match self {
+            CredentialPart::Value(value) => Ok(value.clone()),
+            CredentialPart::File(path) => tokio::fs::read_to_string(path).await
+                .map(|content| content.trim().to_string())
+                .map_err(|e| CredentialsError::provider_error(format!("Cannot read credentials file {path}: {e}"))),
+        }
+    }
+}
+
+impl FileCredentialsProvider {
+    pub fn new(cfg: &ObjStorage) -> FileCredentialsProvider {
+        FileCredentialsProvider {
+            access_key_id: CredentialPart::from_cfg(&cfg.access_key_id, &cfg.access_key_id_file)
+                .unwrap_or(CredentialPart::Value(String::new())),
+            secret_access_key: CredentialPart::from_cfg(&cfg.secret_access_key, &cfg.secret_access_key_file)
+                .unwrap_or(CredentialPart::Value(String::new())),
+            session_token: CredentialPart::from_cfg(&cfg.session_token, &cfg.session_token_file),
+            refresh_interval: Duration::from_secs(cfg.credentials_refresh_secs),
+        }
+    }
+
+    async fn load(&self) -> Result<Credentials, CredentialsError> {
+        let session_token = match &self.session_token {
+            Some(token) => Some(token.read().await?),
+            None => None,
+        };
+        Ok(Credentials::new(
+            self.access_key_id.read().await?,
+            self.secret_access_key.read().await?,
+            session_token,
+            Some(SystemTime::now() + self.refresh_interval),
+            "secret_files",
+        ))
+    }
+}
+
+impl ProvideCredentials for FileCredentialsProvider {
+    fn provide_credentials<'a>(&'a self) -> future::ProvideCredentials<'a>
+    where
+        Self: 'a,
+    {
+        future::ProvideCredentials::new(self.load())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_file_credentials_are_reread() {
+        let dir = std::env::temp_dir().join(format!("media-files-store-creds-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let secret_file = dir.join("secret_access_key");
+        std::fs::write(&secret_file, "secret-1\n").unwrap();
+
+        let provider = FileCredentialsProvider {
+            access_key_id: CredentialPart::Value("key".to_string()),
+            secret_access_key: CredentialPart::File(secret_file.to_string_lossy().to_string()),
+            session_token: None,
+            refresh_interval: Duration::from_secs(60),
+        };
+
+        let creds = provider.provide_credentials().await.unwrap();
+        assert_eq!(creds.access_key_id(), "key");
+        assert_eq!(creds.secret_access_key(), "secret-1");
+        assert!(creds.expiry().is_some());
+
+        std::fs::write(&secret_file, "secret-2").unwrap();
+        let creds = provider.provide_credentials().await.unwrap();
+        assert_eq!(creds.secret_access_key(), "secret-2");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert!(provider.provide_credentials().await.is_err());
+    }
+}
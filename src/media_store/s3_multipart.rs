@@ -0,0 +1,182 @@
+use aws_sdk_s3::{
+    primitives::ByteStream,
+    types::{CompletedMultipartUpload, CompletedPart},
+};
+use bytes::Bytes;
+use futures::{stream::FuturesUnordered, StreamExt};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt},
+    time::Instant,
+};
+
+use crate::configs::MultipartUploadCfg;
+use super::{record_operation, ObjectMetadata, StorageError};
+
+/// S3 does not allow more parts in a single upload
+const MAX_PARTS: i32 = 10_000;
+
+/// Uploads an object in parts, reading the content as the parts are sent,
+/// so that at most `concurrency + 1` parts are kept in memory.
+/// The upload is aborted on failure, so that S3 does not keep (and bill) the uploaded parts.
+pub(super) struct MultipartUpload<'a> {
+    pub client: &'a aws_sdk_s3::Client,
+    pub bucket: &'a str,
+    pub key: &'a str,
+    pub cfg: &'a MultipartUploadCfg,
+}
+
+impl MultipartUpload<'_> {
+    /// `head` is the already read beginning of the content, `rest` is the remaining content
+    pub async fn run(
+        &self,
+        content_type: &str,
+        metadata: &ObjectMetadata,
+        head: Vec<u8>,
+        rest: impl AsyncRead + Unpin,
+    ) -> Result<(), StorageError> {
+        let start = Instant::now();
+        let resp = self.client.create_multipart_upload()
+            .bucket(self.bucket)
+            .key(self.key)
+            .content_type(content_type)
+            .set_metadata(Some(metadata.clone()))
+            .send()
+            .await;
+        record_operation("create_multipart_upload", start, &resp);
+        let upload_id = resp?.upload_id
+            .ok_or_else(|| StorageError::Other("No upload ID in the multipart upload response".to_string()))?;
+
+        let parts = PartReader::new(head, rest, self.cfg.part_size_bytes as usize);
+        let result = async {
+            let parts = self.upload_parts(&upload_id, parts).await?;
+            self.complete(&upload_id, parts).await
+        }.await;
+        if let Err(err) = &result {
+            tracing::warn!(key = self.key, error = %err, "Multipart upload failed, aborting it");
+            self.abort(&upload_id).await;
+        }
+        result
+    }
+
+    async fn upload_parts(&self, upload_id: &str, mut parts: PartReader<impl AsyncRead + Unpin>) -> Result<Vec<CompletedPart>, StorageError> {
+        let mut in_flight = FuturesUnordered::new();
+        let mut completed = Vec::new();
+        let mut part_number = 0;
+        let mut all_read = false;
+        loop {
+            while !all_read && in_flight.len() < self.cfg.concurrency {
+                let part = parts.next().await
+                    .map_err(|e| StorageError::Other(format!("Cannot read the content: {e}")))?;
+                match part {
+                    Some(bytes) => {
+                        part_number += 1;
+                        if part_number > MAX_PARTS {
+                            return Err(StorageError::Other(format!("Object does not fit into {MAX_PARTS} parts")));
+                        }
+                        in_flight.push(self.upload_part(upload_id, part_number, bytes));
+                    },
+                    None => all_read = true,
+                }
+            }
+            match in_flight.next().await {
+                Some(part) => completed.push(part?),
+                None => break,
+            }
+        }
+        completed.sort_by_key(|part| part.part_number);
+        Ok(completed)
+    }
+
+    async fn upload_part(&self, upload_id: &str, part_number: i32, bytes: Bytes) -> Result<CompletedPart, StorageError> {
+        let start = Instant::now();
+        let resp = self.client.upload_part()
+            .bucket(self.bucket)
+            .key(self.key)
+            .upload_id(upload_id)
+            .part_number(part_number)
+            .body(ByteStream::from(bytes))
+            .send()
+            .await;
+        record_operation("upload_part", start, &resp);
+        Ok(CompletedPart::builder()
+            .set_e_tag(resp?.e_tag)
+            .part_number(part_number)
+            .build())
+    }
+
+    async fn complete(&self, upload_id: &str, parts: Vec<CompletedPart>) -> Result<(), StorageError> {
+        let start = Instant::now();
+        let resp = self.client.complete_multipart_upload()
+            .bucket(self.bucket)
+            .key(self.key)
+            .upload_id(upload_id)
+            .multipart_upload(CompletedMultipartUpload::builder().set_parts(Some(parts)).build())
+            .send()
+            .await;
+        record_operation("complete_multipart_upload", start, &resp);
+        resp?;
+        Ok(())
+    }
+
+    /// Failure to abort is only logged, the bucket lifecycle rules are the last resort for such uploads
+    async fn abort(&self, upload_id: &str) {
+        let start = Instant::now();
+        let resp = self.client.abort_multipart_upload()
+            .bucket(self.bucket)
+            .key(self.key)
+            .upload_id(upload_id)
+            .send()
+            .await;
+        record_operation("abort_multipart_upload", start, &resp);
+        if let Err(err) = resp {
+            tracing::error!(key = self.key, upload_id, error = %StorageError::from(err), "Cannot abort multipart upload");
+        }
+    }
+}
+
+/// Splits the content into parts of the given size, the last part may be smaller
+struct PartReader<R> {
+    buffered: Bytes,
+    reader: R,
+    part_size: usize,
+}
+
+impl<R: AsyncRead + Unpin> PartReader<R> {
+    fn new(buffered: Vec<u8>, reader: R, part_size: usize) -> Self {
+        PartReader { buffered: buffered.into(), reader, part_size }
+    }
+
+    async fn next(&mut self) -> std::io::Result<Option<Bytes>> {
+        if self.buffered.len() >= self.part_size {
+            return Ok(Some(self.buffered.split_to(self.part_size)));
+        }
+        let mut part = std::mem::take(&mut self.buffered).to_vec();
+        part.reserve(self.part_size - part.len());
+        (&mut self.reader).take((self.part_size - part.len()) as u64).read_to_end(&mut part).await?;
+        Ok((!part.is_empty()).then(|| part.into()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_content_is_split_into_parts() {
+        let content: Vec<u8> = (0 .. 25).collect();
+        let mut parts = PartReader::new(content[.. 12].to_vec(), &content[12 ..], 5);
+
+        let mut sizes = Vec::new();
+        let mut joined = Vec::new();
+        while let Some(part) = parts.next().await.unwrap() {
+            sizes.push(part.len());
+            joined.extend_from_slice(&part);
+        }
+        assert_eq!(sizes, [5, 5, 5, 5, 5]);
+        assert_eq!(joined, content);
+
+        let mut parts = PartReader::new(vec![1, 2], &[3u8][..], 5);
+        assert_eq!(parts.next().await.unwrap().unwrap().as_ref(), [1, 2, 3]);
+        assert!(parts.next().await.unwrap().is_none());
+    }
+}
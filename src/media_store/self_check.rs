@@ -0,0 +1,42 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use aws_sdk_s3::primitives::ByteStream;
+use bytes::Bytes;
+
+use super::{MediaStore, ObjectMetadata, StorageError};
+
+/// Prefix of the probe objects, they are deleted right away, so nothing should be left under it
+const PROBE_KEY_PREFIX: &str = "self-check";
+
+/// Writes a probe object, reads it back and deletes it, so that missing permissions are reported at startup
+/// instead of failing the first asset. The error names the operation that has failed.
+pub async fn self_check(store: &(dyn MediaStore + Send + Sync)) -> anyhow::Result<()> {
+    let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos()).unwrap_or_default();
+    let key = format!("{PROBE_KEY_PREFIX}/{}-{nanos}", std::process::id());
+    let content = Bytes::from(format!("media-files-store probe {key}"));
+
+    store.put(&key, ByteStream::from(content.clone()), "text/plain", &ObjectMetadata::new()).await
+        .with_context(|| format!("Cannot write the probe object {key}"))?;
+    let read = async { Ok::<_, StorageError>(store.get(&key).await?.bytes.collect().await?) }.await
+        .with_context(|| format!("Cannot read the probe object {key}"))?;
+    anyhow::ensure!(read.into_bytes() == content, "Probe object {key} has been read back with a different content");
+    store.delete(&key).await
+        .with_context(|| format!("Cannot delete the probe object {key}"))?;
+
+    tracing::info!(key, "Media store self-check passed");
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::media_store::MemoryMediaStore;
+
+    #[tokio::test]
+    async fn test_probe_object_is_removed() {
+        let store = MemoryMediaStore::default();
+        self_check(&store).await.unwrap();
+        assert!(store.list(PROBE_KEY_PREFIX, None).await.unwrap().keys.is_empty());
+    }
+}
@@ -1,6 +1,6 @@
 pub const OCTET_STREAM: &str = "application/octet-stream";
 
-#[derive(Hash,PartialEq,Debug)]
+#[derive(Hash,PartialEq,Debug,Clone,Copy)]
 pub enum AssetClass {
     Image,
     Video,
@@ -20,6 +20,8 @@ impl Mime {
             // "image/png" => (),
             // "image/jpeg" => (),
             AssetClass::Image
+        } else if mime.starts_with("video") {
+            AssetClass::Video
         } else {
             AssetClass::Other
         };
@@ -1,8 +1,12 @@
 
 pub fn keccak256_hash_bs58str(s: &str) -> String {
+    keccak256_hash_bs58(s.as_bytes())
+}
+
+pub fn keccak256_hash_bs58(bytes: &[u8]) -> String {
     use sha3::{Digest, Keccak256};
     let mut hasher = Keccak256::default();
-    hasher.update(s.as_bytes());
+    hasher.update(bytes);
     bs58::encode(hasher.finalize().as_slice()).into_string()
 }
 
@@ -12,7 +16,7 @@ pub trait StrUtil {
 
 impl StrUtil for &str {
     fn trim_right_slash(& self) -> & str {
-        let slashes_cnt = self.chars().into_iter().rev().take_while(|c| *c == '/').count();
+        let slashes_cnt = self.chars().rev().take_while(|c| *c == '/').count();
         &self[0 .. self.len() - ('/'.len_utf8() * slashes_cnt)]
     }
 }
@@ -0,0 +1,183 @@
+//! Removal of assets on request, e.g. DMCA notices, offensive content or spam collections.
+//!
+//! Taking an asset down deletes its preview and archived original, and adds its URL and content
+//! to the denylist, so that the pipeline refuses to download them again.
+//! A preview is shared by all the assets with identical content, so all of them are taken down together.
+use std::sync::Arc;
+
+use serde::Serialize;
+
+use crate::{
+    asset_processing::unix_timestamp,
+    das_client::{DasClient, DlOutcome, UrlDlResult},
+    media_store::{self, Denied, KeyKind, MediaMetadata, MediaStore, OriginalsArchive, StorageError},
+    string_util::keccak256_hash_bs58str,
+};
+
+/// Asset to take down, either by its ID (the URL hash) or by its URL
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TakedownTarget {
+    Id(String),
+    Url(String),
+}
+
+#[derive(Debug, Serialize)]
+pub struct TakedownReport {
+    pub id: String,
+    /// Reported to DAS node as taken down. Known if it has been requested, or if it's kept in the preview metadata.
+    pub url: Option<String>,
+    /// Hash of the content, known if the preview has been stored under it. The content is denied as well.
+    pub content_hash: Option<String>,
+}
+
+pub struct Takedown {
+    media_store: Arc<dyn MediaStore + Send + Sync>,
+    originals: Option<Arc<OriginalsArchive>>,
+    /// DAS node is notified only if the pipeline is enabled
+    das_client: Option<Arc<dyn DasClient + Send + Sync>>,
+}
+
+impl Takedown {
+    pub fn new(
+        media_store: Arc<dyn MediaStore + Send + Sync>,
+        originals: Option<Arc<OriginalsArchive>>,
+        das_client: Option<Arc<dyn DasClient + Send + Sync>>,
+    ) -> Takedown {
+        Takedown { media_store, originals, das_client }
+    }
+
+    /// Denylist entries are written before anything is deleted, so that the pipeline does not restore
+    /// the asset in the meantime. Repeating a takedown is harmless, e.g. if it has failed halfway.
+    /// The takedown is complete only once it has been replicated, otherwise the replicas keep serving the asset.
+    pub async fn take_down(&self, target: TakedownTarget, reason: &str) -> Result<TakedownReport, StorageError> {
+        let (id, url) = match target {
+            TakedownTarget::Id(id) => (id, None),
+            TakedownTarget::Url(url) => (keccak256_hash_bs58str(&url), Some(url)),
+        };
+        let store = self.media_store.as_ref();
+        let layout = store.key_layout();
+
+        // The preview knows the content hash and the URL, so it's looked up before being deleted
+        let (content_hash, stored_url) = match store.head_media(&id).await {
+            Ok(info) => (
+                content_hash_of(&info.key),
+                MediaMetadata::from_object_metadata(&info.metadata).map(|metadata| metadata.original_url),
+            ),
+            Err(StorageError::NotFound) => (None, None),
+            Err(err) => return Err(err),
+        };
+        // Long URLs are truncated in the metadata
+        let url = url.or(stored_url.filter(|url| keccak256_hash_bs58str(url) == id));
+
+        let now = unix_timestamp();
+        media_store::deny(store, Denied::Url, &id, reason, now).await?;
+        if let Some(content_hash) = &content_hash {
+            media_store::deny(store, Denied::Content, content_hash, reason, now).await?;
+        }
+
+        for key in layout.read_keys(KeyKind::Media, &id) {
+            store.delete(&key).await?;
+        }
+        if let Some(content_hash) = &content_hash {
+            for key in layout.read_keys(KeyKind::Content, content_hash) {
+                store.delete(&key).await?;
+            }
+        }
+        if let Some(originals) = &self.originals {
+            originals.delete(&id).await?;
+        }
+        store.flush().await?;
+
+        if let (Some(das_client), Some(url)) = (&self.das_client, &url) {
+            das_client.notify_finished(vec![UrlDlResult { url: url.clone(), outcome: DlOutcome::taken_down() }]).await;
+        }
+        tracing::warn!(id, url, content_hash, reason, "Asset has been taken down");
+        Ok(TakedownReport { id, url, content_hash })
+    }
+}
+
+/// Content hash is the last segment of the content keys, whatever the key layout is
+fn content_hash_of(key: &str) -> Option<String> {
+    key.strip_prefix(KeyKind::Content.root())?
+        .strip_prefix('/')?
+        .rsplit('/').next()
+        .map(str::to_string)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use aws_sdk_s3::primitives::ByteStream;
+    use crate::{
+        asset_processing::process_url,
+        configs::AssetProcessorCfg,
+        download::{serve_test_image, DlError, TEST_IMAGE},
+        configs::ReplicaRole,
+        media_store::{test_metadata, MemoryMediaStore, QueueOverflow, Replica, ReplicatedMediaStore, PROCESSING_VERSION},
+        string_util::keccak256_hash_bs58,
+    };
+
+    #[tokio::test]
+    async fn test_take_down() {
+        let store: Arc<dyn MediaStore + Send + Sync> = Arc::new(MemoryMediaStore::default());
+        let base_url = serve_test_image().await;
+        let (url, other_url) = (format!("{base_url}/1.png"), format!("{base_url}/2.png"));
+        let (id, other_id) = (keccak256_hash_bs58str(&url), keccak256_hash_bs58str(&other_url));
+        let content_hash = keccak256_hash_bs58(&std::fs::read(TEST_IMAGE).unwrap());
+        let metadata = |url: &str| test_metadata(url, PROCESSING_VERSION);
+        store.save_content(&content_hash, ByteStream::from_static(b"image"), "image/webp", &metadata(&url)).await.unwrap();
+        store.save_alias(&id, &content_hash, &metadata(&url)).await.unwrap();
+        store.save_alias(&other_id, &content_hash, &metadata(&other_url)).await.unwrap();
+        let takedown = Takedown::new(store.clone(), None, None);
+
+        let report = takedown.take_down(TakedownTarget::Id(id.clone()), "DMCA").await.unwrap();
+        assert_eq!(report.url.as_deref(), Some(url.as_str()));
+        assert_eq!(report.content_hash.as_ref(), Some(&content_hash));
+        assert!(matches!(store.get_media(&id).await, Err(StorageError::NotFound)));
+        assert!(matches!(store.get_media(&other_id).await, Err(StorageError::NotFound)));
+        assert!(media_store::is_denied(store.as_ref(), Denied::Url, &id).await.unwrap());
+        assert!(media_store::is_denied(store.as_ref(), Denied::Content, &content_hash).await.unwrap());
+        assert!(!media_store::is_denied(store.as_ref(), Denied::Url, &other_id).await.unwrap());
+
+        // The other asset is not reported as processed, its content is found denied on download
+        let asset_cfg = AssetProcessorCfg { resize_to: 400, file_max_size_bytes: 1024 * 1024, force_reprocess: false, deduplicate: true };
+        let result = process_url(other_url, store.as_ref(), None, &asset_cfg, &Arc::default()).await;
+        assert!(matches!(result.outcome, DlOutcome::Fail { err: DlError::TakenDown }));
+        assert!(media_store::is_denied(store.as_ref(), Denied::Url, &other_id).await.unwrap());
+
+        // Not processed yet
+        let report = takedown.take_down(TakedownTarget::Url("https://example.com/3.png".to_string()), "spam").await.unwrap();
+        assert_eq!(report.content_hash, None);
+        assert!(media_store::is_denied(store.as_ref(), Denied::Url, &report.id).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_take_down_is_replicated() {
+        let secondary = Arc::new(MemoryMediaStore::default());
+        let store: Arc<dyn MediaStore + Send + Sync> = Arc::new(ReplicatedMediaStore::new(
+            Arc::new(MemoryMediaStore::default()),
+            vec![Replica { name: "secondary".to_string(), role: ReplicaRole::Secondary, store: secondary.clone() }],
+            QueueOverflow::Drop,
+        ));
+        let url = "https://example.com/1.png";
+        let id = keccak256_hash_bs58str(url);
+        store.save_content("hash1", ByteStream::from_static(b"image"), "image/webp", &test_metadata(url, 1)).await.unwrap();
+        store.save_alias(&id, "hash1", &test_metadata(url, 1)).await.unwrap();
+        store.flush().await.unwrap();
+        assert!(secondary.get_media(&id).await.is_ok());
+
+        Takedown::new(store, None, None).take_down(TakedownTarget::Id(id.clone()), "DMCA").await.unwrap();
+        assert!(secondary.list("media/", None).await.unwrap().keys.is_empty());
+        assert!(secondary.list("content/", None).await.unwrap().keys.is_empty());
+        assert!(media_store::is_denied(secondary.as_ref(), Denied::Url, &id).await.unwrap());
+        assert!(media_store::is_denied(secondary.as_ref(), Denied::Content, "hash1").await.unwrap());
+    }
+
+    #[test]
+    fn test_content_hash_of() {
+        assert_eq!(content_hash_of("content/v1/ab/abcd").as_deref(), Some("abcd"));
+        assert_eq!(content_hash_of("content/abcd").as_deref(), Some("abcd"));
+        assert_eq!(content_hash_of("media/abcd"), None);
+        assert_eq!(content_hash_of("contents/abcd"), None);
+    }
+}
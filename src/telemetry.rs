@@ -0,0 +1,143 @@
+//! Tracing setup: log output and optional export of spans to an OpenTelemetry collector (OTLP).
+//!
+//! Trace context is propagated in the W3C `traceparent` format: it is taken
+//! from incoming preview HTTP requests and passed to the DAS node in gRPC metadata.
+use opentelemetry::{
+    global,
+    propagation::Injector,
+    trace::TracerProvider as _,
+    Context, KeyValue,
+};
+use opentelemetry_http::HeaderExtractor;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::{
+    propagation::TraceContextPropagator,
+    runtime,
+    trace::{Sampler, TracerProvider},
+    Resource,
+};
+use tonic::metadata::{MetadataKey, MetadataMap, MetadataValue};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
+
+use crate::configs::{LogFormat, LoggingCfg, TracingCfg};
+
+/// Keeps the OTLP exporter alive, pending spans are flushed on drop
+pub struct TelemetryGuard {
+    tracer_provider: Option<TracerProvider>,
+}
+
+impl Drop for TelemetryGuard {
+    fn drop(&mut self) {
+        if let Some(tracer_provider) = self.tracer_provider.take() {
+            if let Err(err) = tracer_provider.shutdown() {
+                tracing::error!(error = %err, "Failed to flush traces");
+            }
+        }
+    }
+}
+
+/// Installs global tracing subscriber, that writes logs to stdout
+/// and, if enabled, exports spans via OTLP.
+pub fn init(logging_cfg: &LoggingCfg, cfg: &TracingCfg) -> anyhow::Result<TelemetryGuard> {
+    global::set_text_map_propagator(TraceContextPropagator::new());
+
+    let tracer_provider = if cfg.otlp_enabled {
+        let exporter = opentelemetry_otlp::SpanExporter::builder()
+            .with_tonic()
+            .with_endpoint(&cfg.otlp_endpoint)
+            .build()?;
+        let tracer_provider = TracerProvider::builder()
+            .with_batch_exporter(exporter, runtime::Tokio)
+            .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(cfg.sample_ratio))))
+            .with_resource(Resource::new(vec![KeyValue::new("service.name", cfg.service_name.clone())]))
+            .build();
+        global::set_tracer_provider(tracer_provider.clone());
+        Some(tracer_provider)
+    } else {
+        None
+    };
+
+    let otel_layer = tracer_provider.as_ref().map(|tracer_provider|
+        tracing_opentelemetry::layer().with_tracer(tracer_provider.tracer(cfg.service_name.clone()))
+    );
+
+    let filter = match std::env::var(EnvFilter::DEFAULT_ENV) {
+        Ok(directives) => EnvFilter::try_new(directives)?,
+        Err(_) => EnvFilter::try_new(&logging_cfg.level)?,
+    };
+
+    let fmt_layer = match logging_cfg.format {
+        LogFormat::Text => tracing_subscriber::fmt::layer().boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .flatten_event(true)
+            .with_current_span(true)
+            .with_span_list(false)
+            .boxed(),
+    };
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(fmt_layer)
+        .with(otel_layer)
+        .try_init()?;
+
+    Ok(TelemetryGuard { tracer_provider })
+}
+
+/// Makes the current span a continuation of the trace, the given HTTP request belongs to
+pub fn continue_trace_from(headers: &http::HeaderMap, span: &tracing::Span) {
+    let parent_cx = global::get_text_map_propagator(|propagator|
+        propagator.extract(&HeaderExtractor(headers))
+    );
+    // Without a trace context in the request, the extracted context is empty and the span starts a new trace
+    span.set_parent(parent_cx);
+}
+
+/// Adds context of the current span to outgoing gRPC request
+pub fn inject_trace_context<T>(request: &mut tonic::Request<T>) {
+    let cx: Context = tracing::Span::current().context();
+    global::get_text_map_propagator(|propagator|
+        propagator.inject_context(&cx, &mut MetadataInjector(request.metadata_mut()))
+    );
+}
+
+struct MetadataInjector<'a>(&'a mut MetadataMap);
+
+impl Injector for MetadataInjector<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(key), Ok(value)) = (MetadataKey::from_bytes(key.as_bytes()), MetadataValue::try_from(&value)) {
+            self.0.insert(key, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use opentelemetry::{
+        propagation::TextMapPropagator,
+        trace::{SpanContext, SpanId, TraceContextExt, TraceFlags, TraceId, TraceState},
+    };
+
+    #[test]
+    fn test_trace_context_is_injected_into_grpc_metadata() {
+        let span_cx = SpanContext::new(
+            TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap(),
+            SpanId::from_hex("00f067aa0ba902b7").unwrap(),
+            TraceFlags::SAMPLED,
+            true,
+            TraceState::default(),
+        );
+        let cx = Context::new().with_remote_span_context(span_cx);
+
+        let mut request = tonic::Request::new(());
+        TraceContextPropagator::new().inject_context(&cx, &mut MetadataInjector(request.metadata_mut()));
+
+        assert_eq!(
+            request.metadata().get("traceparent").unwrap(),
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"
+        );
+    }
+}
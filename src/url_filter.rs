@@ -0,0 +1,95 @@
+//! Filtering of the URLs before download, by their hosts and by regular expressions.
+//!
+//! Some hosts serve only junk, malware or huge files, there is no point in downloading from them again and again.
+use regex::Regex;
+
+use crate::configs::UrlFilterCfg;
+
+/// Rule of the URLs that match none of the allow rules
+pub const RULE_NOT_ALLOWED: &str = "not_allowed";
+
+/// Compiled `url_filter` config section
+#[derive(Default)]
+pub struct UrlFilter {
+    deny_hosts: Vec<String>,
+    deny_patterns: Vec<Regex>,
+    allow_hosts: Vec<String>,
+    allow_patterns: Vec<Regex>,
+}
+
+impl UrlFilter {
+    /// Fails on an invalid pattern, though patterns are checked by the config validation
+    pub fn new(cfg: &UrlFilterCfg) -> Result<UrlFilter, regex::Error> {
+        let hosts = |hosts: &[String]| hosts.iter().map(|host| host.to_ascii_lowercase()).collect();
+        let patterns = |patterns: &[String]| patterns.iter().map(|pattern| Regex::new(pattern)).collect::<Result<_, _>>();
+        Ok(UrlFilter {
+            deny_hosts: hosts(&cfg.deny_hosts),
+            deny_patterns: patterns(&cfg.deny_patterns)?,
+            allow_hosts: hosts(&cfg.allow_hosts),
+            allow_patterns: patterns(&cfg.allow_patterns)?,
+        })
+    }
+
+    /// Returns the rule the URL is blocked by, e.g. `deny_host:example.com`, or [RULE_NOT_ALLOWED].
+    /// Deny rules take precedence over the allow rules.
+    pub fn blocking_rule(&self, url: &str) -> Option<String> {
+        let host = reqwest::Url::parse(url).ok().and_then(|url| url.host_str().map(str::to_ascii_lowercase));
+        let host_matches = |rule: &&String| host.as_deref().is_some_and(|host| is_same_or_subdomain(host, rule));
+
+        if let Some(rule) = self.deny_hosts.iter().find(host_matches) {
+            return Some(format!("deny_host:{rule}"));
+        }
+        if let Some(rule) = self.deny_patterns.iter().find(|rule| rule.is_match(url)) {
+            return Some(format!("deny_pattern:{}", rule.as_str()));
+        }
+        let has_allow_rules = !self.allow_hosts.is_empty() || !self.allow_patterns.is_empty();
+        let is_allowed = self.allow_hosts.iter().any(|rule| host_matches(&rule))
+            || self.allow_patterns.iter().any(|rule| rule.is_match(url));
+        (has_allow_rules && !is_allowed).then(|| RULE_NOT_ALLOWED.to_string())
+    }
+}
+
+fn is_same_or_subdomain(host: &str, rule: &str) -> bool {
+    host.strip_suffix(rule).is_some_and(|prefix| prefix.is_empty() || prefix.ends_with('.'))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn strings(values: &[&str]) -> Vec<String> {
+        values.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn test_deny_rules() {
+        let filter = UrlFilter::new(&UrlFilterCfg {
+            deny_hosts: strings(&["junk.com"]),
+            deny_patterns: strings(&[r"\.exe$"]),
+            ..Default::default()
+        }).unwrap();
+
+        assert_eq!(filter.blocking_rule("https://junk.com/1.png").as_deref(), Some("deny_host:junk.com"));
+        assert_eq!(filter.blocking_rule("https://CDN.Junk.com/1.png").as_deref(), Some("deny_host:junk.com"));
+        assert_eq!(filter.blocking_rule("https://notjunk.com/1.png"), None);
+        assert_eq!(filter.blocking_rule("https://example.com/setup.exe").as_deref(), Some(r"deny_pattern:\.exe$"));
+        assert_eq!(filter.blocking_rule("not a url"), None);
+    }
+
+    #[test]
+    fn test_allow_rules() {
+        let filter = UrlFilter::new(&UrlFilterCfg {
+            deny_hosts: strings(&["bad.arweave.net"]),
+            allow_hosts: strings(&["arweave.net"]),
+            allow_patterns: strings(&["^ipfs://"]),
+            ..Default::default()
+        }).unwrap();
+
+        assert_eq!(filter.blocking_rule("https://arweave.net/abc"), None);
+        assert_eq!(filter.blocking_rule("ipfs://Qm123"), None);
+        assert_eq!(filter.blocking_rule("https://bad.arweave.net/abc").as_deref(), Some("deny_host:bad.arweave.net"));
+        assert_eq!(filter.blocking_rule("https://example.com/1.png").as_deref(), Some(RULE_NOT_ALLOWED));
+
+        assert!(UrlFilter::new(&UrlFilterCfg { deny_patterns: strings(&["("]), ..Default::default() }).is_err());
+    }
+}